@@ -0,0 +1,72 @@
+use chrono::{Duration, NaiveDateTime};
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use std::io;
+use tvnow::epg::{ChannelList, ChannelSource, Options, Printer, TodayTv, Tv, WeekTv};
+
+const TODAY: &str = include_str!("../tests/fixtures/today.html");
+const BS: &str = include_str!("../tests/fixtures/bs.html");
+
+/// `--week`の8日分として、放送時刻を1日ずつずらした番組表
+/// 同じ番組表を繰り返すと重複した番組として除かれてしまう
+fn week_htmls() -> Vec<String> {
+    (0..8)
+        .map(|days| {
+            TODAY
+                .split('"')
+                .map(
+                    |part| match NaiveDateTime::parse_from_str(part, "%Y%m%d%H%M") {
+                        Ok(time) => (time + Duration::days(days))
+                            .format("%Y%m%d%H%M")
+                            .to_string(),
+                        Err(_) => part.to_string(),
+                    },
+                )
+                .collect::<Vec<_>>()
+                .join("\"")
+        })
+        .collect()
+}
+
+fn parse(c: &mut Criterion) {
+    let options = Options::default();
+    c.bench_function("parse today", |b| {
+        b.iter(|| {
+            TodayTv::from_html::<io::Sink>(ChannelSource::Area(42), black_box(TODAY), &options)
+        })
+    });
+    // `--week`は8日分を解析する
+    let week = week_htmls();
+    c.bench_function("parse week", |b| {
+        b.iter(|| WeekTv::from_htmls::<io::Sink, _>(black_box(&week), &options))
+    });
+}
+
+fn print(c: &mut Criterion) {
+    let options = Options::default();
+    let printers: [(&str, Box<dyn Printer<Vec<u8>>>); 4] = [
+        (
+            "print current",
+            Tv::from_html(ChannelSource::Area(42), TODAY, &options).unwrap(),
+        ),
+        (
+            "print today",
+            TodayTv::from_html(ChannelSource::Area(42), TODAY, &options).unwrap(),
+        ),
+        (
+            "print channels",
+            ChannelList::from_html(ChannelSource::Bs, BS, &options).unwrap(),
+        ),
+        (
+            "print week",
+            WeekTv::from_htmls(&week_htmls(), &options).unwrap(),
+        ),
+    ];
+    for (name, printer) in &printers {
+        c.bench_function(name, |b| {
+            b.iter(|| printer.print(Vec::with_capacity(64 * 1024)).unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, parse, print);
+criterion_main!(benches);
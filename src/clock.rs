@@ -0,0 +1,28 @@
+use chrono::{DateTime, Local};
+use std::fmt;
+
+/// 現在時刻の取得元
+/// 時刻に依存する処理をテストできるように差し替える
+pub trait Clock: fmt::Debug + Send + Sync {
+    fn now(&self) -> DateTime<Local>;
+}
+
+/// システム時刻
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// 常に同じ時刻を返す
+#[derive(Debug, Clone, Copy)]
+pub struct FixedClock(pub DateTime<Local>);
+
+impl Clock for FixedClock {
+    fn now(&self) -> DateTime<Local> {
+        self.0
+    }
+}
@@ -1,13 +1,48 @@
-use crate::epg::{BsTv, Printer, TodayBsTv, TodayTv, Tv, WeekBsTv, WeekTv};
-use anyhow::{anyhow, Result};
+use crate::config::Config;
+use crate::epg::{
+    self, ChannelList, ChannelSource, Format, Options, Page, Printer, Program, SortOrder, Template,
+    TodayTv, Tv, TvnowError, WeekTv,
+};
+use crate::lang::Lang;
+use crate::snapshot::{Entry, Snapshot};
+use crate::theme::Theme;
+use anyhow::{anyhow, Context, Result};
+use chrono::{NaiveDateTime, NaiveTime};
 use colored::*;
-use once_cell::sync::Lazy;
-use std::collections::HashMap;
-use std::io::{self, Write};
-use std::{env, process};
+use crossterm::cursor::MoveTo;
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::tty::IsTty;
+use crossterm::QueueableCommand;
+use once_cell::sync::{Lazy, OnceCell};
+use regex::{Regex, RegexBuilder};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use std::{env, process, thread};
 use structopt::{clap, StructOpt};
 
 const ENV_KEY: &str = "TV_AREA";
+// 番組表を取得するサイトを差し替える環境変数(モックサーバーでのテスト用)
+const BASE_URL_ENV_KEY: &str = "TVNOW_BASE_URL";
+// BS放送・CS放送を表す疑似エリア名
+const BS_AREA: &str = "bs";
+const CS_AREA: &str = "cs";
+// 標準入力から読むエリア名
+const STDIN_AREA: &str = "-";
+const DEFAULT_WATCH_SECS: u64 = 60;
+// bangumi.orgへの負荷を避けるための最短更新間隔
+const MIN_WATCH_SECS: u64 = 10;
+const DEFAULT_PER_PAGE: usize = 50;
+// エリア一覧の列の間隔
+const AREA_COLUMN_GAP: usize = 2;
+// 番組開始の何分前に通知するか
+const NOTIFY_LEAD_MINUTES: i64 = 5;
+// bangumi.orgが番組表を提供している前後の日数
+const MAX_RELATIVE_DAYS: i64 = 7;
+
+// Ctrl-Cで落とすまで繰り返す
+static RUNNING: AtomicBool = AtomicBool::new(true);
 
 pub struct Cli<T, U> {
     out_stream: T,
@@ -22,12 +57,24 @@ impl<T: Write, U: Write> Cli<T, U> {
         }
     }
 
+    /// 引数の文字列を介さずに設定して実行する
+    pub fn builder(out: T, err: U) -> CliBuilder<T, U> {
+        CliBuilder {
+            cli: Cli::new(out, err),
+            areas: vec![],
+            mode: Mode::Current,
+            options: None,
+            format: None,
+            no_color: false,
+        }
+    }
+
     pub fn execute(&mut self, args: impl Iterator<Item = String>) -> ExitCode {
         match self.run(args) {
             Ok(_) => ExitCode::Normal,
             Err(e) => {
                 writeln!(self.err_stream, "{}", e).unwrap();
-                ExitCode::Abnormal
+                ExitCode::from(&e)
             }
         }
     }
@@ -36,54 +83,381 @@ impl<T: Write, U: Write> Cli<T, U> {
         // ANSIエスケープコードに基づいて出力を正しく色付けしないWindows 10環境で必要
         #[cfg(target_os = "windows")]
         control::set_virtual_terminal(true).unwrap();
+        epg::check_selectors()?;
         let opt = self.get_opt(args)?;
+        init_logger(opt.verbose, opt.quiet);
+        if opt.ascii {
+            control::set_override(false);
+        }
+        let config = load_config()?;
+        let theme = opt
+            .theme
+            .or(config.theme)
+            .or_else(Theme::from_env)
+            .unwrap_or_default();
         if opt.area {
             return {
-                self.print_areas();
+                if opt.grouped {
+                    self.print_grouped_areas(theme);
+                } else if opt.ids {
+                    self.print_area_ids();
+                } else {
+                    // パイプ先では1行に1エリアのままにする
+                    let width = io::stdout()
+                        .is_tty()
+                        .then(|| crossterm::terminal::size().ok())
+                        .flatten()
+                        .map(|(w, _)| w as usize);
+                    self.print_areas(theme, width);
+                }
                 Ok(())
             };
         }
-        let default_area = env::var(ENV_KEY).ok();
-        let default_area = default_area.as_deref().unwrap_or("tokyo");
-        let mut area_id = self.get_area_id(default_area)?;
-        if let Some(area_name) = opt.area_name.as_deref() {
-            if let Some(&id) = AREA_MAP.get(area_name) {
-                area_id = id;
+        if let Some(names) = &opt.save_favorites {
+            return self.save_favorites(names, opt.lang());
+        }
+        let mut options = Options::from(&opt);
+        options.theme = theme;
+        if options.channel_names.is_empty() && !opt.no_favorites {
+            options.channel_names = config.favorites;
+        }
+        let default_area = default_area();
+        let stdin_area = if opt.area_names.iter().any(|name| name == STDIN_AREA) {
+            Some(read_area(io::stdin().lock(), options.lang)?)
+        } else {
+            None
+        };
+        let mut area_names = opt.area_names();
+        if area_names.is_empty() {
+            area_names.push(&default_area);
+        }
+        if let Some(stdin_area) = &stdin_area {
+            for name in area_names.iter_mut().filter(|name| **name == STDIN_AREA) {
+                *name = stdin_area.as_str();
+            }
+        }
+        let areas = area_names
+            .into_iter()
+            .map(|name| self.get_source(name, &options).map(|source| (name, source)))
+            .collect::<Result<Vec<_>>>()?;
+        if opt.print_url || opt.dry_run {
+            let mode = Mode::from(&opt);
+            let urls = areas
+                .iter()
+                .flat_map(|&(_, source)| urls(source, mode, &options))
+                .collect::<Vec<_>>();
+            // --dry-runはURLだけが出力なので標準出力に書く
+            if opt.dry_run {
+                for url in urls {
+                    writeln!(self.out_stream, "{}", url)?;
+                }
+                return Ok(());
+            }
+            for url in urls {
+                writeln!(self.err_stream, "{}", url)?;
+            }
+        }
+        // 複数のエリアでは他のエリアのチャンネルかもしれないので確かめない
+        if areas.len() > 1 {
+            options.check_channel_names = false;
+        }
+        if let Some(keyword) = &opt.notify {
+            return self.notify(&areas, keyword, &options);
+        }
+        if opt.diff {
+            return self.diff(&areas, &options);
+        }
+        if opt.only_current_channel {
+            return self.print_current_title(&areas, &options);
+        }
+        if opt.now_playing_json {
+            return self.print_now_playing_json(&areas, &options);
+        }
+        if let Some(secs) = opt.watch {
+            return self.watch(&areas, secs.unwrap_or(DEFAULT_WATCH_SECS), &options);
+        }
+        let mode = Mode::from(&opt);
+        if mode == Mode::Week {
+            // 時間のかかる1週間分の取得を中断できるようにする
+            set_interrupt_handler()?;
+        }
+        if opt.raw_html {
+            return self.print_raw_html(&areas, mode, &options);
+        }
+        let names = areas.iter().map(|&(name, _)| name);
+        let count = if opt.count_only {
+            // 番組は捨てて件数だけを書き、警告やエラーはそのまま出す
+            let count = Cli::builder(io::sink(), &mut self.err_stream)
+                .areas(names)
+                .mode(mode)
+                .options(options.clone())
+                .run()?;
+            writeln!(self.out_stream, "{}", count)?;
+            count
+        } else {
+            Cli::builder(&mut self.out_stream, &mut self.err_stream)
+                .areas(names)
+                .mode(mode)
+                .options(options.clone())
+                .run()?
+        };
+        if opt.fail_if_empty && count == 0 {
+            return Err(anyhow!(options.lang.no_programs()));
+        }
+        Ok(())
+    }
+
+    /// 取得したHTMLを解析せずにそのまま書き出す
+    fn print_raw_html(
+        &mut self,
+        areas: &[(&str, ChannelSource)],
+        mode: Mode,
+        options: &Options,
+    ) -> Result<()> {
+        for &(_, source) in areas {
+            let urls = urls(source, mode, options);
+            let bodies = epg::fetch_bodies(urls.clone(), options)?;
+            for (url, body) in urls.iter().zip(bodies) {
+                writeln!(self.out_stream, "{}", raw_html_marker(url))?;
+                self.out_stream.write_all(body.as_bytes())?;
+                if !body.ends_with('\n') {
+                    writeln!(self.out_stream)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// お気に入りのチャンネルを設定ファイルに保存する
+    fn save_favorites(&mut self, names: &str, lang: Lang) -> Result<()> {
+        let path = Config::path().ok_or_else(|| anyhow!(lang.config_dir_not_found()))?;
+        let mut config =
+            Config::load(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        config.favorites = names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(String::from)
+            .collect();
+        config
+            .save(&path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        writeln!(self.out_stream, "{}", path.display())?;
+        Ok(())
+    }
+
+    fn watch(
+        &mut self,
+        areas: &[(&str, ChannelSource)],
+        secs: u64,
+        options: &Options,
+    ) -> Result<()> {
+        set_interrupt_handler()?;
+        let interval = Duration::from_secs(secs.max(MIN_WATCH_SECS));
+        while RUNNING.load(Ordering::SeqCst) {
+            // 取得が終わってから画面を書き換えることでちらつきを抑える
+            let mut buf = vec![];
+            print_programs(&mut buf, areas, Mode::Current, options)?;
+            // パイプ先には制御文字を書かない
+            if io::stdout().is_tty() {
+                self.out_stream
+                    .queue(Clear(ClearType::All))?
+                    .queue(MoveTo(0, 0))?;
+            }
+            self.out_stream.write_all(&buf)?;
+            self.out_stream.flush()?;
+            let start = Instant::now();
+            while RUNNING.load(Ordering::SeqCst) && start.elapsed() < interval {
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+        Ok(())
+    }
+
+    /// `--channel`のチャンネルで放送中の番組名だけを装飾なしで表示する
+    /// 複数のチャンネルが一致すれば最初に放送中のもの、どれも放送していなければエラー
+    fn print_current_title(
+        &mut self,
+        areas: &[(&str, ChannelSource)],
+        options: &Options,
+    ) -> Result<()> {
+        for &(_, source) in areas {
+            if let Some(program) = epg::fetch_current(source, options)?.into_iter().next() {
+                writeln!(self.out_stream, "{}", program.title)?;
+                return Ok(());
+            }
+        }
+        Err(anyhow!(options.lang.off_air()))
+    }
+
+    /// `--channel`のチャンネルで放送中の番組をステータスバー向けのJSONで表示する
+    /// どれも放送していなければ放送していない旨の文言
+    fn print_now_playing_json(
+        &mut self,
+        areas: &[(&str, ChannelSource)],
+        options: &Options,
+    ) -> Result<()> {
+        let mut current = None;
+        for &(_, source) in areas {
+            current = epg::fetch_current(source, options)?.into_iter().next();
+            if current.is_some() {
+                break;
+            }
+        }
+        let json = now_playing_json(current.as_ref(), options);
+        writeln!(self.out_stream, "{}", serde_json::to_string(&json)?)?;
+        Ok(())
+    }
+
+    /// 現在放送中の番組を前回の実行時と比べ、新しく始まった番組を強調表示する
+    fn diff(&mut self, areas: &[(&str, ChannelSource)], options: &Options) -> Result<()> {
+        let path = Snapshot::path().ok_or_else(|| anyhow!(options.lang.cache_dir_not_found()))?;
+        let mut snapshot =
+            Snapshot::load(&path).with_context(|| format!("failed to read {}", path.display()))?;
+        let mut buf = io::BufWriter::new(&mut self.out_stream);
+        for &(name, source) in areas {
+            let programs = epg::fetch_current(source, options)?;
+            let entries = programs.iter().map(Entry::from).collect::<Vec<_>>();
+            // 初回は比べる相手がいないので強調しない
+            let previous = snapshot
+                .areas
+                .get(name)
+                .map(|previous| previous.iter().collect::<HashSet<_>>());
+            if areas.len() > 1 {
+                writeln!(buf, "{}", header(name, Mode::Current, options).bold())?;
+            }
+            let color = options.theme.channel(source);
+            for (program, entry) in programs.iter().zip(&entries) {
+                let started = previous
+                    .as_ref()
+                    .map_or(false, |previous| !previous.contains(entry));
+                if started {
+                    writeln!(
+                        buf,
+                        "{} {} {}",
+                        program.channel.color(color),
+                        program.title.color(options.theme.highlight()).bold(),
+                        options.lang.started()
+                    )?;
+                } else {
+                    writeln!(buf, "{} {}", program.channel.color(color), program.title)?;
+                }
+            }
+            snapshot.areas.insert(name.to_string(), entries);
+        }
+        buf.flush()?;
+        snapshot
+            .save(&path)
+            .with_context(|| format!("failed to write {}", path.display()))?;
+        Ok(())
+    }
+
+    fn notify(
+        &mut self,
+        areas: &[(&str, ChannelSource)],
+        keyword: &str,
+        options: &Options,
+    ) -> Result<()> {
+        set_interrupt_handler()?;
+        let options = Options {
+            search: Some(keyword_regex(keyword, false)),
+            ..options.clone()
+        };
+        let lead = chrono::Duration::minutes(NOTIFY_LEAD_MINUTES);
+        let interval = Duration::from_secs(DEFAULT_WATCH_SECS);
+        let mut notified = HashSet::new();
+        while RUNNING.load(Ordering::SeqCst) {
+            for &(_, source) in areas {
+                for program in epg::fetch_upcoming(source, &options, lead)? {
+                    if !notified.insert(program.clone()) {
+                        continue;
+                    }
+                    let start = program.start.format("%R");
+                    writeln!(
+                        self.out_stream,
+                        "{} {} {}",
+                        start, program.channel, program.title
+                    )?;
+                    notify_rust::Notification::new()
+                        .summary(&program.title)
+                        .body(&format!("{} {}", start, program.channel))
+                        .show()?;
+                }
+            }
+            self.out_stream.flush()?;
+            let start = Instant::now();
+            while RUNNING.load(Ordering::SeqCst) && start.elapsed() < interval {
+                thread::sleep(Duration::from_millis(100));
             }
         }
-        self.get_tv_printer(area_id, &opt)?
-            .print(&mut self.out_stream);
         Ok(())
     }
 
     fn get_opt(&self, args: impl Iterator<Item = String>) -> Result<Opt> {
-        Ok(Opt::from_iter_safe(args)?)
+        let opt = Opt::from_iter_safe(args)?;
+        opt.validate()?;
+        Ok(opt)
     }
 
-    fn get_area_id(&self, default: &str) -> Result<u8> {
+    /// エリア名から番組表の取得元を求める
+    fn get_source(&self, default: &str, options: &Options) -> Result<ChannelSource> {
+        match default {
+            BS_AREA => return Ok(ChannelSource::Bs),
+            CS_AREA => return Ok(ChannelSource::Cs),
+            _ => {}
+        }
         AREA_MAP
             .get(default)
-            .copied()
-            .ok_or_else(|| anyhow!("{} is not in the area", default.bright_yellow()))
-    }
-
-    fn get_tv_printer<W>(&self, area_id: u8, opt: &Opt) -> Result<Box<dyn Printer<W>>>
-    where
-        W: Write,
-    {
-        create_printer(area_id, opt)
+            .map(|&id| ChannelSource::Area(id))
+            .ok_or_else(|| {
+                let area = default.color(options.theme.area()).to_string();
+                anyhow::Error::new(TvnowError::UnknownArea(default.to_string()))
+                    .context(options.lang.not_in_area(&area))
+            })
     }
 
-    fn print_areas(&mut self) {
+    /// `width`が分かれば端末の幅に収まるように複数列で表示する
+    fn print_areas(&mut self, theme: Theme, width: Option<usize>) {
         let mut areas = AREA_MAP.iter().map(|(&k, _)| k).collect::<Vec<_>>();
         let mut buf = io::BufWriter::new(&mut self.out_stream);
+        areas.extend([BS_AREA, CS_AREA]);
         areas.sort();
-        areas.iter().for_each(|&a| {
-            match a {
-                "bs" => writeln!(buf, "{}", "bs".bright_yellow()).unwrap(),
-                _ => writeln!(buf, "{}", a).unwrap(),
-            };
-        });
+        let column = areas.iter().map(|a| a.len()).max().unwrap_or(0) + AREA_COLUMN_GAP;
+        let columns = width.map_or(1, |width| (width / column).max(1));
+        for row in area_rows(&areas, columns) {
+            let last = row.len() - 1;
+            for (i, &a) in row.iter().enumerate() {
+                match a {
+                    BS_AREA | CS_AREA => write!(buf, "{}", a.color(theme.area())).unwrap(),
+                    _ => write!(buf, "{}", a).unwrap(),
+                };
+                if i < last {
+                    write!(buf, "{}", " ".repeat(column - a.len())).unwrap();
+                }
+            }
+            writeln!(buf).unwrap();
+        }
+    }
+
+    /// エリア名とbangumi.orgのエリアIDをID順に表示する
+    /// BS・CSはIDが無いので含めない
+    fn print_area_ids(&mut self) {
+        let mut buf = io::BufWriter::new(&mut self.out_stream);
+        for (area, id) in area_ids() {
+            writeln!(buf, "{}\t{}", area, id).unwrap();
+        }
+    }
+
+    fn print_grouped_areas(&mut self, theme: Theme) {
+        let mut buf = io::BufWriter::new(&mut self.out_stream);
+        writeln!(buf, "{}", BS_AREA.color(theme.area())).unwrap();
+        writeln!(buf, "{}", CS_AREA.color(theme.area())).unwrap();
+        for (region, areas) in REGIONS {
+            let mut areas = areas.to_vec();
+            areas.sort();
+            writeln!(buf, "{}", region.bold()).unwrap();
+            areas.iter().for_each(|a| writeln!(buf, "  {}", a).unwrap());
+        }
     }
 }
 
@@ -97,29 +471,851 @@ struct Opt {
     /// Prints a week program
     #[structopt(short, long, conflicts_with_all(&["after", "area"]))]
     week: bool,
-    /// Prints area list
-    #[structopt(short, long, conflicts_with_all(&["today", "week"]))]
+    /// Prints area list (in columns on a terminal)
+    #[structopt(short, long, conflicts_with_all(&["today", "week", "AREA"]))]
     area: bool,
+    /// Groups the area list by region (with --area)
+    #[structopt(long, requires = "area")]
+    grouped: bool,
+    /// Prints each area with its bangumi.org area id, ordered by id (with --area)
+    #[structopt(long, requires = "area", conflicts_with = "grouped")]
+    ids: bool,
+
+    /// Refreshes the current program every SECS seconds, given as --watch=SECS (default 60)
+    #[structopt(
+        long,
+        value_name = "SECS",
+        require_equals = true,
+        conflicts_with_all(&["today", "week", "area"])
+    )]
+    watch: Option<Option<u64>>,
+
+    /// Highlights the current programs that started since the previous --diff run
+    #[structopt(
+        long,
+        conflicts_with_all(&["today", "week", "channels", "area", "watch", "notify", "format", "ndjson", "template"])
+    )]
+    diff: bool,
+
+    /// Fetches program guides from URL instead of https://bangumi.org, like a mirror or a caching proxy
+    #[structopt(long, value_name = "URL", parse(try_from_str = parse_base_url))]
+    base_url: Option<String>,
+
+    /// Text shown for channels that are not broadcasting (overrides --lang, ignored with --quiet or --hide-offair)
+    #[structopt(long, value_name = "TEXT", conflicts_with_all(&["today", "week", "channels", "area"]))]
+    no_program_placeholder: Option<String>,
+
+    /// Prints only the current programs ending within MIN minutes, soonest first
+    #[structopt(
+        long,
+        value_name = "MIN",
+        conflicts_with_all(&["today", "week", "channels", "area", "diff"])
+    )]
+    ending_within: Option<i64>,
+
+    /// Sends a desktop notification when a program containing KEYWORD is about to start
+    #[structopt(long, value_name = "KEYWORD", conflicts_with_all(&["today", "week", "area", "watch"]))]
+    notify: Option<String>,
+
+    /// Prints only the channels with remote control number N
+    #[structopt(long, value_name = "N", visible_alias = "ch")]
+    channel_number: Option<u32>,
+    /// Prints only the channels whose name contains NAME (comma-separated, overrides favorites)
+    #[structopt(long, value_name = "NAME", use_delimiter = true, number_of_values = 1)]
+    channel: Vec<String>,
+    /// Prints only the title of the current program on the --channel channel for status bars and fails when it is not broadcasting
+    #[structopt(
+        long,
+        requires = "channel",
+        conflicts_with_all(&["today", "week", "area", "channels", "watch", "notify", "diff", "format", "ndjson", "template", "compact", "count_only"])
+    )]
+    only_current_channel: bool,
+    /// Prints the current program on the --channel channel as {"text", "tooltip"} JSON for waybar or polybar modules
+    #[structopt(
+        long,
+        requires = "channel",
+        conflicts_with_all(&["today", "week", "area", "channels", "watch", "notify", "diff", "format", "ndjson", "template", "compact", "count_only", "only_current_channel"])
+    )]
+    now_playing_json: bool,
+    /// Saves channel names (comma-separated) to the config file as the default --channel. An empty value clears them
+    #[structopt(long, value_name = "NAMES", conflicts_with_all(&["today", "week", "area", "channels", "watch", "notify"]))]
+    save_favorites: Option<String>,
+    /// Ignores the favorites saved in the config file
+    #[structopt(long, conflicts_with_all(&["channel", "save_favorites"]))]
+    no_favorites: bool,
+
+    /// Prints only the channel names and numbers of the area
+    #[structopt(
+        long,
+        visible_alias = "list-channels",
+        conflicts_with_all(&["today", "week", "area", "watch", "notify", "compact"])
+    )]
+    channels: bool,
+
+    /// Prints only the number of matching programs
+    #[structopt(
+        long,
+        conflicts_with_all(&["area", "watch", "notify", "diff", "dry_run", "format", "ndjson", "template", "summary", "compact"])
+    )]
+    count_only: bool,
+
+    /// Exits with an error when no programs were printed
+    #[structopt(long, conflicts_with_all(&["area", "watch", "notify", "dry_run"]))]
+    fail_if_empty: bool,
+
+    /// Prints the URLs to fetch to stderr
+    #[structopt(long, conflicts_with = "notify")]
+    print_url: bool,
+    /// Prints the URLs to fetch and exits without fetching
+    #[structopt(long, visible_alias = "url", conflicts_with = "notify")]
+    dry_run: bool,
+    /// Prints the fetched HTML without parsing it, each page after a <!-- tvnow: URL --> comment (for bug reports)
+    #[structopt(
+        long,
+        conflicts_with_all(&["area", "watch", "notify", "diff", "dry_run", "count_only", "format", "ndjson", "template"])
+    )]
+    raw_html: bool,
+
+    /// Excludes programs that have already started (with --today)
+    #[structopt(long, requires = "today")]
+    since_now: bool,
+
+    /// Prints the current programs on a single line
+    #[structopt(long, conflicts_with_all(&["today", "week", "area"]))]
+    compact: bool,
+
+    /// Highlights titles containing KEYWORD
+    #[structopt(long, value_name = "KEYWORD")]
+    highlight: Option<String>,
+
+    /// Wraps long titles at the terminal width
+    #[structopt(long, conflicts_with = "compact")]
+    wrap: bool,
+    /// Truncates titles with an ellipsis to fit COLS columns (default: terminal width)
+    #[structopt(long, value_name = "COLS", conflicts_with_all(&["compact", "wrap"]))]
+    truncate: Option<Option<usize>>,
+
+    /// Prints a description under each program, fetching detail pages when the guide has none
+    #[structopt(long, conflicts_with = "compact")]
+    detail: bool,
+
+    /// Output format
+    #[structopt(
+        long,
+        value_name = "FORMAT",
+        possible_values(&["text", "json", "csv", "markdown", "ical", "tsv", "ndjson"]),
+        conflicts_with_all(&["compact", "summary"])
+    )]
+    format: Option<Format>,
+    /// Prints one JSON object per line, same as --format ndjson
+    #[structopt(long, conflicts_with_all(&["format", "compact", "summary"]))]
+    ndjson: bool,
+
+    /// Prints --format timestamps in UTC with a Z suffix instead of Japan time (json, ndjson, csv, tsv, ical)
+    #[structopt(long)]
+    utc: bool,
+
+    /// Prints each program with TEMPLATE like "{start} {channel} {title}" (channel, number, date, start, end, duration, title, detail)
+    #[structopt(
+        long,
+        value_name = "TEMPLATE",
+        conflicts_with_all(&["format", "ndjson", "compact", "summary", "group_by_time"])
+    )]
+    template: Option<Template>,
+
+    /// Orders --today and --week output by channel or by start time across channels
+    #[structopt(long, value_name = "ORDER", possible_values(&["channel", "time"]))]
+    sort: Option<SortOrder>,
+
+    /// Orders channels by remote control number instead of the site's order
+    #[structopt(long)]
+    sort_channels: bool,
+
+    /// Prints each program on one line with its date, sorted by time across channels (with --week)
+    #[structopt(
+        long,
+        requires = "week",
+        conflicts_with_all(&["format", "ndjson", "template", "group_by_time", "sort", "detail", "summary"])
+    )]
+    flat: bool,
+
+    /// Prints the programs as a grid of hourly time slots by channel, one grid per day (with --week)
+    #[structopt(
+        long,
+        requires = "week",
+        conflicts_with_all(&["format", "ndjson", "merge", "summary", "sort", "page", "per_page"])
+    )]
+    group_by_time: bool,
+
+    /// Prints page N of the week program (with --week)
+    #[structopt(long, value_name = "N", requires = "week", parse(try_from_str = parse_positive))]
+    page: Option<usize>,
+    /// Number of programs per page (default 50, with --week)
+    #[structopt(long, value_name = "COUNT", requires = "week", parse(try_from_str = parse_positive))]
+    per_page: Option<usize>,
+    /// Number of week pages fetched at the same time (default 3)
+    #[structopt(long, value_name = "N", parse(try_from_str = parse_positive))]
+    concurrency: Option<usize>,
+
+    /// Merges consecutive programs with the same title (with --week)
+    #[structopt(long, requires = "week")]
+    merge: bool,
+
+    /// Prints only programs starting at or after DATETIME like 2024-01-05T18:00 (with --week)
+    #[structopt(long, value_name = "DATETIME", requires = "week", parse(try_from_str = parse_datetime))]
+    since: Option<NaiveDateTime>,
+    /// Prints only programs starting before DATETIME like 2024-01-07T23:00 (with --week)
+    #[structopt(long, value_name = "DATETIME", requires = "week", parse(try_from_str = parse_datetime))]
+    until: Option<NaiveDateTime>,
+    /// Prints only programs starting at or after TIME like 20:00, where times before the day change hour are after midnight (with --today)
+    #[structopt(long, value_name = "TIME", requires = "today", parse(try_from_str = parse_time))]
+    start_after: Option<NaiveTime>,
+
+    /// Hides channels that are not broadcasting and silences stderr output other than errors
+    #[structopt(short, long)]
+    quiet: bool,
+    /// Hides channels that are not broadcasting
+    #[structopt(long, conflicts_with_all(&["today", "week", "channels", "area"]))]
+    hide_offair: bool,
+
+    /// Prints the number of programs and airtime per channel (with --today or --week)
+    #[structopt(long)]
+    summary: bool,
+
+    /// Language of messages (ja, en). Defaults to Japanese for a Japanese system locale and English otherwise
+    #[structopt(long, value_name = "LANG", possible_values(&["ja", "en"]))]
+    lang: Option<Lang>,
+
+    /// Color theme for dark or light terminal backgrounds. Defaults to the config file, then COLORFGBG
+    #[structopt(long, value_name = "THEME", possible_values(&["dark", "light"]))]
+    theme: Option<Theme>,
+
+    /// Prints only programs whose title contains KEYWORD (case-insensitive)
+    #[structopt(long, value_name = "KEYWORD")]
+    search: Option<String>,
+    /// Matches --search case-sensitively
+    #[structopt(long, requires = "search")]
+    case_sensitive: bool,
+    /// Prints only programs whose title matches PATTERN
+    #[structopt(long, value_name = "PATTERN", conflicts_with = "search", parse(try_from_str = Regex::new))]
+    search_regex: Option<Regex>,
+
+    /// Fails instead of printing best-effort output when the program guide looks unexpected
+    #[structopt(long)]
+    strict: bool,
+
+    /// Logs requests and fetch, parse and print timings to stderr (-vv for more detail)
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+
+    /// Prints yesterday's program (with --today)
+    #[structopt(long, requires = "today", conflicts_with = "tomorrow")]
+    yesterday: bool,
+    /// Prints tomorrow's program (with --today)
+    #[structopt(long, requires = "today")]
+    tomorrow: bool,
+    /// Prints the program N days from today like -2 or 3 (with --today)
+    #[structopt(
+        long,
+        value_name = "N",
+        allow_hyphen_values = true,
+        requires = "today",
+        conflicts_with_all(&["yesterday", "tomorrow"]),
+        parse(try_from_str = parse_relative_day)
+    )]
+    relative_day: Option<i64>,
+
+    /// Hour at which the broadcast day starts (0-23, default 5)
+    #[structopt(long, value_name = "H", parse(try_from_str = parse_hour))]
+    guide_start_hour: Option<u32>,
+
+    /// Prints only ASCII separators and no colors for restricted logging environments
+    #[structopt(long)]
+    ascii: bool,
+
+    /// Prints BS programs instead of an area (same as the bs area)
+    #[structopt(long, conflicts_with_all(&["area", "AREA"]))]
+    bs: bool,
+    /// Prints CS programs instead of an area (same as the cs area)
+    #[structopt(long, conflicts_with_all(&["area", "AREA"]))]
+    cs: bool,
+
+    #[structopt(name = "AREA")]
+    area_names: Vec<String>,
+}
+
+impl Opt {
+    /// 指定されたエリア名
+    /// `--bs`と`--cs`はエリア`bs`、`cs`として最後に加える
+    fn area_names(&self) -> Vec<&str> {
+        let mut names = self
+            .area_names
+            .iter()
+            .map(|s| s.as_str())
+            .collect::<Vec<_>>();
+        if self.bs {
+            names.push(BS_AREA);
+        }
+        if self.cs {
+            names.push(CS_AREA);
+        }
+        names
+    }
+
+    /// structoptの競合ルールでは表現できない引数の組み合わせを検証する
+    fn validate(&self) -> clap::Result<()> {
+        let names = self.area_names();
+        for (i, name) in names.iter().enumerate() {
+            if names[..i].contains(name) {
+                return Err(clap::Error::with_description(
+                    &format!("The area '{}' was provided more than once", name),
+                    clap::ErrorKind::ArgumentConflict,
+                ));
+            }
+        }
+        if self.utc && self.format.is_none() && !self.ndjson {
+            return Err(clap::Error::with_description(
+                self.lang().utc_requires_format(),
+                clap::ErrorKind::MissingRequiredArgument,
+            ));
+        }
+        Ok(())
+    }
+
+    /// `--lang`の指定がなければロケールの言語
+    fn lang(&self) -> Lang {
+        self.lang.unwrap_or_else(Lang::from_env)
+    }
+
+    /// `--ndjson`は`--format ndjson`と同じ
+    fn format(&self) -> Format {
+        if self.ndjson {
+            Format::Ndjson
+        } else {
+            self.format.unwrap_or_default()
+        }
+    }
+}
+
+impl From<&Opt> for Options {
+    fn from(opt: &Opt) -> Self {
+        let default = Options::default();
+        let width = crossterm::terminal::size().ok().map(|(w, _)| w as usize);
+        Options {
+            since_now: opt.since_now,
+            compact: opt.compact,
+            highlight: opt.highlight.clone(),
+            guide_start_hour: opt.guide_start_hour.unwrap_or(default.guide_start_hour),
+            day_offset: match (opt.relative_day, opt.yesterday, opt.tomorrow) {
+                (Some(days), _, _) => days,
+                (_, true, _) => -1,
+                (_, _, true) => 1,
+                _ => default.day_offset,
+            },
+            detail: opt.detail || opt.template.as_ref().map_or(false, Template::needs_detail),
+            merge: opt.merge,
+            hide_offair: opt.quiet || opt.hide_offair,
+            summary: opt.summary,
+            lang: opt.lang(),
+            search: match (&opt.search, &opt.search_regex) {
+                (Some(keyword), _) => Some(keyword_regex(keyword, opt.case_sensitive)),
+                (_, Some(pattern)) => Some(pattern.clone()),
+                _ => None,
+            },
+            strict: opt.strict,
+            check_channel_names: !opt.channel.is_empty(),
+            width,
+            wrap: opt.wrap,
+            truncate: match opt.truncate {
+                Some(Some(cols)) => Some(cols),
+                Some(None) => width,
+                None => None,
+            },
+            // 全チャンネル・全日の番組を日時順に並べる
+            sort: if opt.flat {
+                SortOrder::Time
+            } else {
+                opt.sort.unwrap_or_default()
+            },
+            clock: default.clock,
+            format: opt.format(),
+            group_by_time: opt.group_by_time,
+            channel_number: opt.channel_number,
+            channel_names: opt
+                .channel
+                .iter()
+                .map(|name| name.trim().to_string())
+                .filter(|name| !name.is_empty())
+                .collect(),
+            page: match (opt.page, opt.per_page) {
+                (None, None) => None,
+                (number, size) => Some(Page {
+                    number: number.unwrap_or(1),
+                    size: size.unwrap_or(DEFAULT_PER_PAGE),
+                }),
+            },
+            sort_channels: opt.sort_channels,
+            // パイプやリダイレクト先に進捗を混ぜない
+            progress: !opt.quiet && io::stderr().is_tty(),
+            concurrency: opt.concurrency.unwrap_or(default.concurrency),
+            template: opt.template.clone(),
+            since: opt.since,
+            until: opt.until,
+            start_after: opt.start_after,
+            base_url: opt
+                .base_url
+                .clone()
+                .or_else(|| {
+                    env::var(BASE_URL_ENV_KEY)
+                        .ok()
+                        .filter(|url| !url.is_empty())
+                })
+                .unwrap_or(default.base_url),
+            // 設定ファイルと合わせてrunで決める
+            theme: opt.theme.unwrap_or(default.theme),
+            ascii: opt.ascii,
+            flat: opt.flat,
+            ending_within: opt.ending_within,
+            off_air_placeholder: opt.no_program_placeholder.clone(),
+            utc: opt.utc,
+        }
+    }
+}
+
+/// `-v`の数に応じたログ出力を設定する
+/// 指定がなければ`RUST_LOG`に従う
+/// `--quiet`ならエラー以外のログを出さない
+fn init_logger(verbose: u8, quiet: bool) {
+    let mut builder = env_logger::Builder::from_default_env();
+    match verbose {
+        _ if quiet => {
+            builder.filter_level(log::LevelFilter::Error);
+        }
+        // 番組表の提供範囲外などの警告は表示する
+        0 if env::var_os("RUST_LOG").is_none() => {
+            builder.filter_module("tvnow", log::LevelFilter::Warn);
+        }
+        0 => {}
+        1 => {
+            builder.filter_module("tvnow", log::LevelFilter::Info);
+        }
+        2 => {
+            builder.filter_module("tvnow", log::LevelFilter::Debug);
+        }
+        _ => {
+            builder.filter_level(log::LevelFilter::Trace);
+        }
+    }
+    // テストなどで2回目以降の初期化は無視する
+    let _ = builder.try_init();
+}
+
+/// `--search`のキーワードを正規表現にする
+fn keyword_regex(keyword: &str, case_sensitive: bool) -> Regex {
+    RegexBuilder::new(&regex::escape(keyword))
+        .case_insensitive(!case_sensitive)
+        .build()
+        .expect("escaped keyword is a valid pattern")
+}
+
+fn parse_positive(s: &str) -> Result<usize, String> {
+    match s.parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("{} is not a positive number", s)),
+    }
+}
+
+fn parse_datetime(s: &str) -> Result<NaiveDateTime, String> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M")
+        .map_err(|_| format!("{} is not a datetime like 2024-01-05T18:00", s))
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(s, "%H:%M").map_err(|_| format!("{} is not a time like 20:00", s))
+}
+
+fn parse_base_url(s: &str) -> Result<String, String> {
+    if s.starts_with("http://") || s.starts_with("https://") {
+        Ok(s.to_string())
+    } else {
+        Err(format!("{} is not an http(s) URL", s))
+    }
+}
+
+fn parse_hour(s: &str) -> Result<u32, String> {
+    match s.parse::<u32>() {
+        Ok(hour) if hour < 24 => Ok(hour),
+        _ => Err(format!("{} is not an hour between 0 and 23", s)),
+    }
+}
+
+fn parse_relative_day(s: &str) -> Result<i64, String> {
+    match s.parse::<i64>() {
+        Ok(days) if days.abs() <= MAX_RELATIVE_DAYS => Ok(days),
+        Ok(_) => Err(format!(
+            "{} is out of range: bangumi.org serves programs from {} to {} days from today",
+            s, -MAX_RELATIVE_DAYS, MAX_RELATIVE_DAYS
+        )),
+        Err(_) => Err(format!("{} is not a number of days", s)),
+    }
+}
+
+/// 表示モード
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// 現在放送中の番組
+    Current,
+    /// 今日の番組
+    Today,
+    /// 1週間分の番組
+    Week,
+    /// チャンネル一覧
+    Channels,
+    /// 今日のこれから始まる番組
+    Next,
+    /// 今日の指定した時刻以降に始まる番組
+    At(NaiveTime),
+}
+
+impl Mode {
+    /// 見出しに表示する名前
+    fn name(&self) -> &'static str {
+        match self {
+            Mode::Current => "now",
+            Mode::Today => "today",
+            Mode::Week => "week",
+            Mode::Channels => "channels",
+            Mode::Next => "next",
+            Mode::At(_) => "at",
+        }
+    }
+
+    /// 表示モードに合わせた表示オプション
+    /// `Next`と`At`は今日の番組表を絞り込んで表示する
+    fn options(&self, options: &Options) -> Options {
+        match *self {
+            Mode::Next => Options {
+                since_now: true,
+                ..options.clone()
+            },
+            Mode::At(time) => Options {
+                start_after: Some(time),
+                ..options.clone()
+            },
+            _ => options.clone(),
+        }
+    }
+}
+
+impl From<&Opt> for Mode {
+    fn from(opt: &Opt) -> Self {
+        if opt.channels {
+            Mode::Channels
+        } else if opt.today {
+            Mode::Today
+        } else if opt.week {
+            Mode::Week
+        } else {
+            Mode::Current
+        }
+    }
+}
+
+/// エリアの指定がないときのエリア
+fn default_area() -> String {
+    env::var(ENV_KEY).unwrap_or_else(|_| "tokyo".to_string())
+}
+
+fn print_programs<W: Write>(
+    mut w: W,
+    areas: &[(&str, ChannelSource)],
+    mode: Mode,
+    options: &Options,
+) -> Result<usize> {
+    // 見出しは複数のエリアを区別するため。JSONやCSVなどの出力は壊さない
+    let with_header = areas.len() > 1 && !options.is_formatted();
+    let mut count = 0;
+    for &(name, source) in areas {
+        let started = Instant::now();
+        let printer = create_printer(source, mode, options)?;
+        log::info!("fetched and parsed {} in {:?}", name, started.elapsed());
+        if with_header {
+            writeln!(w, "{}", header(name, mode, options).bold())?;
+        }
+        let started = Instant::now();
+        let printed = printer.print(&mut w)?;
+        log::info!(
+            "printed {} programs of {} in {:?}",
+            printed,
+            name,
+            started.elapsed()
+        );
+        count += printed;
+    }
+    Ok(count)
+}
+
+/// ID順のエリア名とID
+/// 同じIDのエリアはエリア名順
+fn area_ids() -> Vec<(&'static str, u8)> {
+    let mut areas = AREA_MAP
+        .iter()
+        .map(|(&area, &id)| (area, id))
+        .collect::<Vec<_>>();
+    areas.sort_by_key(|&(area, id)| (id, area));
+    areas
+}
+
+/// エリア名を`columns`列に上から下へ並べた行
+fn area_rows<'a>(areas: &[&'a str], columns: usize) -> Vec<Vec<&'a str>> {
+    let rows = areas.len().div_ceil(columns);
+    (0..rows)
+        .map(|row| areas.iter().skip(row).step_by(rows).copied().collect())
+        .collect()
+}
+
+/// Ctrl-Cで繰り返しと取得中の番組表を止める
+/// ハンドラはプロセスに一つしか登録できないので一度だけ登録する
+fn set_interrupt_handler() -> Result<()> {
+    static HANDLER: OnceCell<()> = OnceCell::new();
+    HANDLER.get_or_try_init(|| {
+        ctrlc::set_handler(|| {
+            RUNNING.store(false, Ordering::SeqCst);
+            epg::interrupt();
+        })
+    })?;
+    Ok(())
+}
+
+/// エリア名と表示モード、放送日の見出し
+fn header(name: &str, mode: Mode, options: &Options) -> String {
+    let date = match mode {
+        Mode::Current => format!(" {}", options.clock.now().format("%Y-%m-%d %H:%M")),
+        Mode::Today => format!(" {}", TodayTv::date(options)),
+        Mode::Week => {
+            let (first, last) = WeekTv::dates(options);
+            format!(" {} {} {}", first, options.separator(), last)
+        }
+        Mode::Channels => String::new(),
+        Mode::Next => format!(" {}", TodayTv::date(options)),
+        Mode::At(time) => format!(" {} {}", TodayTv::date(options), time.format("%H:%M")),
+    };
+    format!("== {} {}{} ==", name, mode.name(), date)
+}
+
+/// 1行目をエリア名として読む
+fn read_area<R: BufRead>(mut r: R, lang: Lang) -> Result<String> {
+    let mut line = String::new();
+    r.read_line(&mut line)?;
+    let area = line.trim();
+    if area.is_empty() {
+        return Err(anyhow!(lang.no_stdin_area()));
+    }
+    Ok(area.to_string())
+}
+
+/// 設定ファイルの内容
+/// 設定ファイルが無ければ既定値
+fn load_config() -> Result<Config> {
+    match Config::path() {
+        Some(path) => {
+            Config::load(&path).with_context(|| format!("failed to read {}", path.display()))
+        }
+        None => Ok(Config::default()),
+    }
+}
+
+/// 表示モードで取得する番組表のURL
+fn urls(source: ChannelSource, mode: Mode, options: &Options) -> Vec<String> {
+    match mode {
+        Mode::Current => Tv::urls(source, options),
+        Mode::Today => TodayTv::urls(source, options),
+        Mode::Week => WeekTv::urls(source, options),
+        Mode::Channels => ChannelList::urls(source, options),
+        Mode::Next | Mode::At(_) => TodayTv::urls(source, &mode.options(options)),
+    }
+}
 
-    #[structopt(name = "AREA", min_values = 0, max_values = 1)]
-    area_name: Option<String>,
+/// `--now-playing-json`の`{"text", "tooltip"}`
+fn now_playing_json(program: Option<&Program>, options: &Options) -> serde_json::Value {
+    match program {
+        Some(program) => {
+            let time = |t: Option<NaiveDateTime>| {
+                t.map_or("--:--".to_string(), |t| t.format("%R").to_string())
+            };
+            let mut tooltip = format!(
+                "{} {} {} {}",
+                time(program.start),
+                options.separator(),
+                time(program.end),
+                program.title
+            );
+            if let Some(detail) = &program.detail {
+                tooltip = format!("{}\n{}", tooltip, detail);
+            }
+            serde_json::json!({
+                "text": format!("{}: {}", program.channel, program.title),
+                "tooltip": tooltip,
+            })
+        }
+        None => serde_json::json!({
+            "text": options.off_air(),
+            "tooltip": "",
+        }),
+    }
 }
 
-fn create_printer<T: Write>(area: u8, opt: &Opt) -> Result<Box<dyn Printer<T>>> {
-    match area {
-        0 if opt.today => TodayBsTv::init(),
-        0 if opt.week => WeekBsTv::init(),
-        0 => BsTv::init(),
-        i if opt.today => TodayTv::init(i),
-        i if opt.week => WeekTv::init(i),
-        i => Tv::init(i),
+/// `--raw-html`で各ページの前に書く取得元のURL
+fn raw_html_marker(url: &str) -> String {
+    format!("<!-- tvnow: {} -->", url)
+}
+
+fn create_printer<T: Write>(
+    source: ChannelSource,
+    mode: Mode,
+    options: &Options,
+) -> Result<Box<dyn Printer<T>>> {
+    match mode {
+        Mode::Current => Tv::init(source, options),
+        Mode::Today => TodayTv::init(source, options),
+        Mode::Week => WeekTv::init(source, options),
+        Mode::Channels => ChannelList::init(source, options),
+        Mode::Next | Mode::At(_) => TodayTv::init(source, &mode.options(options)),
     }
 }
 
+/// `Cli::builder`で組み立てる実行の設定
+pub struct CliBuilder<T, U> {
+    cli: Cli<T, U>,
+    areas: Vec<String>,
+    mode: Mode,
+    options: Option<Options>,
+    format: Option<Format>,
+    no_color: bool,
+}
+
+impl<T: Write, U: Write> CliBuilder<T, U> {
+    /// 表示するエリア(`bs`・`cs`も可)
+    /// 指定がなければ`TV_AREA`か東京
+    pub fn area(mut self, name: impl Into<String>) -> Self {
+        self.areas.push(name.into());
+        self
+    }
+
+    /// 表示するエリアをまとめて指定する
+    pub fn areas<I>(mut self, names: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.areas.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// 細かい設定をまとめて指定する
+    /// `format`などの個別の設定は呼ぶ順によらずこちらより優先する
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = Some(options);
+        self
+    }
+
+    pub fn no_color(mut self, no_color: bool) -> Self {
+        self.no_color = no_color;
+        self
+    }
+
+    /// `options`と個別の設定をまとめる
+    fn build(&self) -> Options {
+        let mut options = self.options.clone().unwrap_or_default();
+        if let Some(format) = self.format {
+            options.format = format;
+        }
+        options
+    }
+
+    /// 番組表を書き出し、書き出した番組数を返す
+    pub fn run(&mut self) -> Result<usize> {
+        if self.no_color {
+            control::set_override(false);
+        }
+        let options = self.build();
+        let default_area = default_area();
+        let names = if self.areas.is_empty() {
+            vec![default_area.as_str()]
+        } else {
+            self.areas.iter().map(String::as_str).collect()
+        };
+        let areas = names
+            .into_iter()
+            .map(|name| {
+                self.cli
+                    .get_source(name, &options)
+                    .map(|source| (name, source))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        print_programs(&mut self.cli.out_stream, &areas, self.mode, &options)
+    }
+
+    /// `Cli::execute`と同じくエラーを書き出して終了コードを返す
+    pub fn execute(&mut self) -> ExitCode {
+        match self.run() {
+            Ok(_) => ExitCode::Normal,
+            Err(e) => {
+                writeln!(self.cli.err_stream, "{}", e).unwrap();
+                ExitCode::from(&e)
+            }
+        }
+    }
+}
+
+impl Cli<Vec<u8>, Vec<u8>> {
+    /// 標準出力・標準エラー出力の代わりにメモリへ書き出して実行する
+    /// テストや他のツールへの組み込み用
+    pub fn run_to_string<I, S>(args: I) -> Output
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut cli = Cli::new(vec![], vec![]);
+        let code = cli.execute(args.into_iter().map(Into::into));
+        Output {
+            code,
+            out: String::from_utf8_lossy(&cli.out_stream).into_owned(),
+            err: String::from_utf8_lossy(&cli.err_stream).into_owned(),
+        }
+    }
+}
+
+/// `Cli::run_to_string`の実行結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub code: ExitCode,
+    pub out: String,
+    pub err: String,
+}
+
 #[derive(PartialOrd, PartialEq, Debug, Clone, Copy)]
 pub enum ExitCode {
     Normal = 0,
     Abnormal = 1,
+    /// 通信エラー
+    Network = 2,
+    /// 番組表の解析エラー
+    Parse = 3,
+    /// 引数の誤り
+    Argument = 4,
+    /// Ctrl-Cで中断した
+    Interrupted = 130,
 }
 
 impl ExitCode {
@@ -128,9 +1324,109 @@ impl ExitCode {
     }
 }
 
+impl From<&anyhow::Error> for ExitCode {
+    fn from(e: &anyhow::Error) -> Self {
+        if let Some(e) = e.downcast_ref::<clap::Error>() {
+            return match e.kind {
+                clap::ErrorKind::HelpDisplayed | clap::ErrorKind::VersionDisplayed => {
+                    ExitCode::Normal
+                }
+                _ => ExitCode::Argument,
+            };
+        }
+        match e.chain().find_map(|e| e.downcast_ref::<TvnowError>()) {
+            Some(
+                TvnowError::Http(_)
+                | TvnowError::Offline(_)
+                | TvnowError::BadStatus(_)
+                | TvnowError::Timeout,
+            ) => ExitCode::Network,
+            Some(TvnowError::Parse(_) | TvnowError::Decode(_) | TvnowError::Structure) => {
+                ExitCode::Parse
+            }
+            Some(TvnowError::UnknownArea(_) | TvnowError::UnknownChannel { .. }) => {
+                ExitCode::Argument
+            }
+            Some(TvnowError::Interrupted) => ExitCode::Interrupted,
+            Some(TvnowError::Strict(_) | TvnowError::Selector(_)) | None => ExitCode::Abnormal,
+        }
+    }
+}
+
+/// 地方ごとのエリア
+const REGIONS: [(&str, &[&str]); 8] = [
+    (
+        "Hokkaido",
+        &[
+            "sapporo",
+            "hakodate",
+            "asahikawa",
+            "obihiro",
+            "kushiro",
+            "kitami",
+            "muroran",
+        ],
+    ),
+    (
+        "Tohoku",
+        &[
+            "aomori",
+            "iwate",
+            "miyagi",
+            "akita",
+            "yamagata",
+            "fukushima",
+        ],
+    ),
+    (
+        "Kanto",
+        &[
+            "tokyo", "kanagawa", "saitama", "chiba", "ibaragi", "tochigi", "gumma",
+        ],
+    ),
+    (
+        "Chubu",
+        &[
+            "yamanashi",
+            "nagano",
+            "niigata",
+            "aichi",
+            "ishikawa",
+            "shizuoka",
+            "fukui",
+            "toyama",
+            "gifu",
+        ],
+    ),
+    (
+        "Kinki",
+        &[
+            "mie", "osaka", "kyoto", "hyogo", "wakayama", "nara", "shiga",
+        ],
+    ),
+    (
+        "Chugoku",
+        &["hiroshima", "okayama", "shimane", "tottori", "yamaguchi"],
+    ),
+    ("Shikoku", &["ehime", "kagawa", "tokushima", "kochi"]),
+    (
+        "Kyushu-Okinawa",
+        &[
+            "fukuoka",
+            "kitakyushu",
+            "kumamoto",
+            "nagasaki",
+            "kagoshima",
+            "miyazaki",
+            "oita",
+            "saga",
+            "okinawa",
+        ],
+    ),
+];
+
 static AREA_MAP: Lazy<HashMap<&'static str, u8>> = Lazy::new(|| {
     let m = [
-        ("bs", 0),
         ("sapporo", 1),
         ("hakodate", 8),
         ("asahikawa", 3),
@@ -196,7 +1492,10 @@ static AREA_MAP: Lazy<HashMap<&'static str, u8>> = Lazy::new(|| {
 mod tests {
 
     use super::*;
+    use crate::clock::FixedClock;
+    use chrono::{Local, TimeZone};
     use colored::control::set_override;
+    use std::sync::Arc;
 
     #[test]
     fn test_tv_works() {
@@ -243,19 +1542,586 @@ mod tests {
         let mut cli = Cli::new(vec![], vec![]);
         let args = vec!["tvnow".to_string(), "tokyo".to_string(), "-wt".to_string()];
         let result = cli.execute(args.into_iter());
-        assert_eq!(result, ExitCode::Abnormal);
+        assert_eq!(result, ExitCode::Argument);
 
         let args = vec!["tvnow".to_string(), "tokyo".to_string(), "-1".to_string()];
         let result = cli.execute(args.into_iter());
-        assert_eq!(result, ExitCode::Abnormal);
+        assert_eq!(result, ExitCode::Argument);
+
+        let args = vec![
+            "tvnow".to_string(),
+            "tokyo".to_string(),
+            "hogehoge".to_string(),
+        ];
+        let result = cli.execute(args.into_iter());
+        assert_eq!(result, ExitCode::Argument);
+    }
+    #[test]
+    fn test_builder_works() {
+        let mut out: Vec<u8> = vec![];
+        let count = Cli::builder(&mut out, vec![])
+            .area("tokyo")
+            .mode(Mode::Channels)
+            .no_color(true)
+            .run()
+            .unwrap();
+        let out_string = String::from_utf8(out).unwrap();
+        // エリアが一つなら見出しは無い
+        assert!(!out_string.starts_with("=="));
+        assert_eq!(out_string.lines().count(), count);
+
+        let mut err: Vec<u8> = vec![];
+        let result = Cli::builder(vec![], &mut err)
+            .area("hogehoge")
+            .format(Format::Json)
+            .execute();
+        assert_eq!(result, ExitCode::Argument);
+        assert!(!err.is_empty());
+    }
+    #[test]
+    fn test_builder_order_works() {
+        let options = Options {
+            detail: true,
+            ..Options::default()
+        };
+        let builder = Cli::builder(vec![], vec![])
+            .format(Format::Json)
+            .options(options.clone());
+        assert_eq!(builder.build().format, Format::Json);
+        assert!(builder.build().detail);
+        let builder = Cli::builder(vec![], vec![])
+            .options(options)
+            .format(Format::Json);
+        assert_eq!(builder.build().format, Format::Json);
+        assert!(builder.build().detail);
+    }
+    #[test]
+    fn test_run_to_string_captures_err() {
+        let output = Cli::run_to_string(vec![
+            "tvnow".to_string(),
+            "--bs".to_string(),
+            "bs".to_string(),
+        ]);
+        assert_eq!(output.code, ExitCode::Argument);
+        assert!(output.out.is_empty());
+        assert!(!output.err.is_empty());
+    }
+    #[test]
+    fn test_conflicting_args_works() {
+        let mut cli = Cli::new(vec![], vec![]);
+        let args = vec!["tvnow".to_string(), "-a".to_string(), "tokyo".to_string()];
+        let result = cli.execute(args.into_iter());
+        assert_eq!(result, ExitCode::Argument);
+
+        let args = vec![
+            "tvnow".to_string(),
+            "bs".to_string(),
+            "tokyo".to_string(),
+            "bs".to_string(),
+        ];
+        let result = cli.execute(args.into_iter());
+        assert_eq!(result, ExitCode::Argument);
+
+        let args = vec![
+            "tvnow".to_string(),
+            "--notify".to_string(),
+            "ニュース".to_string(),
+            "-t".to_string(),
+        ];
+        let result = cli.execute(args.into_iter());
+        assert_eq!(result, ExitCode::Argument);
+    }
+    #[test]
+    fn test_dry_run_works() {
+        let mut out: Vec<u8> = vec![];
+        let mut err: Vec<u8> = vec![];
+        let mut cli = Cli::new(&mut out, &mut err);
+        let args = vec![
+            "tvnow".to_string(),
+            "--dry-run".to_string(),
+            "-w".to_string(),
+            "tokyo".to_string(),
+            "bs".to_string(),
+        ];
+        let result = cli.execute(args.into_iter());
+        assert_eq!(result, ExitCode::Normal);
+        assert!(err.is_empty());
+
+        let out_string = String::from_utf8(out).unwrap();
+        let lines = out_string.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 16);
+        assert!(lines[..8].iter().all(|l| l.ends_with("ggm_group_id=42")));
+        assert!(lines[8..]
+            .iter()
+            .all(|l| l.starts_with("https://bangumi.org/epg/bs?")));
+    }
+    #[test]
+    fn test_bs_flag_works() {
+        let output = Cli::run_to_string(["tvnow", "--dry-run", "--bs"]);
+        assert_eq!(output.code, ExitCode::Normal);
+        assert_eq!(output.out, "https://bangumi.org/epg/bs\n");
+        assert!(output.err.is_empty());
 
+        let output = Cli::run_to_string(["tvnow", "--dry-run", "--bs", "--cs"]);
+        assert_eq!(output.code, ExitCode::Normal);
+        assert_eq!(
+            output.out,
+            "https://bangumi.org/epg/bs\nhttps://bangumi.org/epg/cs\n"
+        );
+
+        let mut cli = Cli::new(vec![], vec![]);
+        let args = vec!["tvnow".to_string(), "--bs".to_string(), "bs".to_string()];
+        let result = cli.execute(args.into_iter());
+        assert_eq!(result, ExitCode::Argument);
+        let args = vec!["tvnow".to_string(), "--cs".to_string(), "tokyo".to_string()];
+        let result = cli.execute(args.into_iter());
+        assert_eq!(result, ExitCode::Argument);
+    }
+    #[test]
+    fn test_multiple_areas_works() {
+        // カラー化無効
+        set_override(false);
+        let mut out: Vec<u8> = vec![];
+        let mut err: Vec<u8> = vec![];
+        let mut cli = Cli::new(&mut out, &mut err);
         let args = vec![
             "tvnow".to_string(),
             "tokyo".to_string(),
-            "osaka".to_string(),
+            "kanagawa".to_string(),
+        ];
+        let result = cli.execute(args.into_iter());
+        assert_eq!(result, ExitCode::Normal);
+
+        let out_string = String::from_utf8(out).unwrap();
+        let headers = out_string
+            .lines()
+            .filter(|s| s.starts_with("=="))
+            .collect::<Vec<_>>();
+        assert_eq!(headers.len(), 2);
+        assert!(headers[0].starts_with("== tokyo now "));
+        assert!(headers[1].starts_with("== kanagawa now "));
+    }
+    #[test]
+    fn test_header_works() {
+        let now = Local.with_ymd_and_hms(2024, 1, 2, 1, 30, 0).unwrap();
+        let options = Options {
+            clock: Arc::new(FixedClock(now)),
+            ..Options::default()
+        };
+        assert_eq!(
+            header("tokyo", Mode::Current, &options),
+            "== tokyo now 2024-01-02 01:30 =="
+        );
+        // 深夜は前日の放送日
+        assert_eq!(
+            header("tokyo", Mode::Today, &options),
+            "== tokyo today 2024-01-01 =="
+        );
+        assert_eq!(
+            header("bs", Mode::Week, &options),
+            "== bs week 2024-01-01 ~ 2024-01-08 =="
+        );
+        assert_eq!(
+            header("osaka", Mode::Channels, &options),
+            "== osaka channels =="
+        );
+        assert_eq!(
+            header("tokyo", Mode::Next, &options),
+            "== tokyo next 2024-01-01 =="
+        );
+        let time = NaiveTime::from_hms_opt(20, 0, 0).unwrap();
+        assert_eq!(
+            header("tokyo", Mode::At(time), &options),
+            "== tokyo at 2024-01-01 20:00 =="
+        );
+    }
+
+    #[test]
+    fn test_mode_options_works() {
+        let options = Options::default();
+        assert!(Mode::Next.options(&options).since_now);
+        let time = NaiveTime::from_hms_opt(20, 0, 0).unwrap();
+        assert_eq!(Mode::At(time).options(&options).start_after, Some(time));
+        let today = Mode::Today.options(&options);
+        assert!(!today.since_now);
+        assert_eq!(today.start_after, None);
+    }
+    #[test]
+    fn test_regions_cover_areas() {
+        let mut grouped = REGIONS
+            .iter()
+            .flat_map(|(_, areas)| areas.iter().copied())
+            .collect::<Vec<_>>();
+        grouped.sort();
+        let mut areas = AREA_MAP.keys().copied().collect::<Vec<_>>();
+        areas.sort();
+        assert_eq!(grouped, areas);
+    }
+    #[test]
+    fn test_area_rows_works() {
+        let areas = ["aichi", "akita", "aomori", "bs", "chiba"];
+        assert_eq!(
+            area_rows(&areas, 1),
+            vec![
+                vec!["aichi"],
+                vec!["akita"],
+                vec!["aomori"],
+                vec!["bs"],
+                vec!["chiba"]
+            ]
+        );
+        assert_eq!(
+            area_rows(&areas, 2),
+            vec![vec!["aichi", "bs"], vec!["akita", "chiba"], vec!["aomori"]]
+        );
+        assert_eq!(
+            area_rows(&areas, 10),
+            vec![vec!["aichi", "akita", "aomori", "bs", "chiba"]]
+        );
+    }
+    #[test]
+    fn test_grouped_areas_works() {
+        // カラー化無効
+        set_override(false);
+        let mut out: Vec<u8> = vec![];
+        let mut err: Vec<u8> = vec![];
+        let mut cli = Cli::new(&mut out, &mut err);
+        let args = vec![
+            "tvnow".to_string(),
+            "-a".to_string(),
+            "--grouped".to_string(),
+        ];
+        let result = cli.execute(args.into_iter());
+        assert_eq!(result, ExitCode::Normal);
+        let out_string = String::from_utf8(out).unwrap();
+        assert!(out_string.starts_with("bs\ncs\nHokkaido\n  asahikawa\n"));
+        assert!(out_string.ends_with("Kyushu-Okinawa\n  fukuoka\n  kagoshima\n  kitakyushu\n  kumamoto\n  miyazaki\n  nagasaki\n  oita\n  okinawa\n  saga\n"));
+    }
+    #[test]
+    fn test_area_ids_works() {
+        let areas = area_ids();
+        assert_eq!(areas.len(), AREA_MAP.len());
+        assert!(areas.windows(2).all(|w| w[0].1 <= w[1].1));
+        assert_eq!(areas[0], ("sapporo", 1));
+
+        let output = Cli::run_to_string(["tvnow", "-a", "--ids"]);
+        assert_eq!(output.code, ExitCode::Normal);
+        assert!(output.out.starts_with("sapporo\t1\n"));
+        assert!(output.out.contains("\ntokyo\t42\n"));
+        assert!(Opt::from_iter_safe(["tvnow", "--ids"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "-a", "--ids", "--grouped"]).is_err());
+    }
+    #[test]
+    fn test_count_only_option_works() {
+        assert!(
+            Opt::from_iter_safe(["tvnow", "-t", "--count-only"])
+                .unwrap()
+                .count_only
+        );
+        assert!(Opt::from_iter_safe(["tvnow", "--count-only", "--format", "json"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "--count-only", "--watch"]).is_err());
+    }
+    #[test]
+    fn test_watch_option_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "--watch"]).unwrap();
+        assert_eq!(opt.watch, Some(None));
+        let opt = Opt::from_iter_safe(["tvnow", "--watch=30"]).unwrap();
+        assert_eq!(opt.watch, Some(Some(30)));
+        // 値は`=`で続けるので、後ろのエリア名を間隔と取り違えない
+        let opt = Opt::from_iter_safe(["tvnow", "--watch", "tokyo"]).unwrap();
+        assert_eq!(opt.watch, Some(None));
+        assert_eq!(opt.area_names, ["tokyo"]);
+    }
+    #[test]
+    fn test_hide_offair_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "--hide-offair"]).unwrap();
+        assert!(Options::from(&opt).hide_offair);
+        assert!(!opt.quiet);
+        // --quietも放送していないチャンネルを表示しない
+        let opt = Opt::from_iter_safe(["tvnow", "--quiet"]).unwrap();
+        assert!(Options::from(&opt).hide_offair);
+        assert!(Opt::from_iter_safe(["tvnow", "-t", "--hide-offair"]).is_err());
+    }
+    #[test]
+    fn test_base_url_option_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "--base-url", "http://localhost:8080/"]).unwrap();
+        assert_eq!(Options::from(&opt).base_url, "http://localhost:8080/");
+        assert!(Opt::from_iter_safe(["tvnow", "--base-url", "localhost"]).is_err());
+    }
+    #[test]
+    fn test_ch_alias_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "--ch", "4", "--channel", "日テレ"]).unwrap();
+        let options = Options::from(&opt);
+        assert_eq!(options.channel_number, Some(4));
+        assert_eq!(options.channel_names, vec!["日テレ".to_string()]);
+        assert!(Opt::from_iter_safe(["tvnow", "--ch", "NHK"]).is_err());
+    }
+    #[test]
+    fn test_raw_html_option_works() {
+        assert!(
+            Opt::from_iter_safe(["tvnow", "-w", "--raw-html"])
+                .unwrap()
+                .raw_html
+        );
+        assert!(Opt::from_iter_safe(["tvnow", "--raw-html", "--format", "json"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "--raw-html", "--dry-run"]).is_err());
+        assert_eq!(
+            raw_html_marker("https://bangumi.org/epg/bs"),
+            "<!-- tvnow: https://bangumi.org/epg/bs -->"
+        );
+    }
+    #[test]
+    fn test_utc_option_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "-t", "--format", "csv", "--utc"]).unwrap();
+        assert!(Options::from(&opt).utc);
+        let opt = Opt::from_iter_safe(["tvnow", "-t", "--ndjson", "--utc"]).unwrap();
+        assert!(opt.validate().is_ok());
+        let opt = Opt::from_iter_safe(["tvnow", "-t", "--utc", "--lang", "ja"]).unwrap();
+        assert!(opt
+            .validate()
+            .unwrap_err()
+            .message
+            .ends_with("--utc には --format か --ndjson が必要です"));
+    }
+    #[test]
+    fn test_ndjson_option_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "-w", "--ndjson"]).unwrap();
+        assert_eq!(Options::from(&opt).format, Format::Ndjson);
+        let opt = Opt::from_iter_safe(["tvnow", "-w"]).unwrap();
+        assert_eq!(Options::from(&opt).format, Format::Text);
+        assert!(Opt::from_iter_safe(["tvnow", "--ndjson", "--format", "json"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "--ndjson", "--compact"]).is_err());
+    }
+    #[test]
+    fn test_only_current_channel_option_works() {
+        assert!(
+            Opt::from_iter_safe(["tvnow", "--only-current-channel", "--channel", "NHK"])
+                .unwrap()
+                .only_current_channel
+        );
+        assert!(Opt::from_iter_safe(["tvnow", "--only-current-channel"]).is_err());
+        assert!(
+            Opt::from_iter_safe(["tvnow", "-t", "--only-current-channel", "--channel", "NHK"])
+                .is_err()
+        );
+    }
+    #[test]
+    fn test_start_after_option_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "-t", "--start-after", "20:00"]).unwrap();
+        assert_eq!(
+            Options::from(&opt).start_after,
+            NaiveTime::from_hms_opt(20, 0, 0)
+        );
+        assert!(Opt::from_iter_safe(["tvnow", "-t", "--start-after", "8pm"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "-t", "--start-after", "25:00"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "--start-after", "20:00"]).is_err());
+    }
+    #[test]
+    fn test_now_playing_json_works() {
+        let options = Options::default();
+        let program = Program {
+            channel: "[1] NHK総合".to_string(),
+            number: Some("1".to_string()),
+            name: "NHK総合".to_string(),
+            start: NaiveDateTime::parse_from_str("202401011900", "%Y%m%d%H%M").ok(),
+            end: NaiveDateTime::parse_from_str("202401012000", "%Y%m%d%H%M").ok(),
+            title: "ニュース7".to_string(),
+            detail: None,
+        };
+        assert_eq!(
+            now_playing_json(Some(&program), &options),
+            serde_json::json!({
+                "text": "[1] NHK総合: ニュース7",
+                "tooltip": "19:00 ~ 20:00 ニュース7",
+            })
+        );
+        assert_eq!(
+            now_playing_json(None, &options),
+            serde_json::json!({"text": "現在放送していません", "tooltip": ""})
+        );
+        assert!(Opt::from_iter_safe(["tvnow", "--now-playing-json"]).is_err());
+        assert!(
+            Opt::from_iter_safe(["tvnow", "--now-playing-json", "--channel", "NHK"])
+                .unwrap()
+                .now_playing_json
+        );
+    }
+    #[test]
+    fn test_diff_option_works() {
+        assert!(
+            Opt::from_iter_safe(["tvnow", "--diff", "osaka"])
+                .unwrap()
+                .diff
+        );
+        assert!(Opt::from_iter_safe(["tvnow", "--diff", "-t"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "--diff", "--format", "json"]).is_err());
+    }
+    #[test]
+    fn test_theme_option_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "--theme", "light"]).unwrap();
+        assert_eq!(Options::from(&opt).theme, Theme::Light);
+        let opt = Opt::from_iter_safe(["tvnow"]).unwrap();
+        assert_eq!(opt.theme, None);
+        assert!(Opt::from_iter_safe(["tvnow", "--theme", "blue"]).is_err());
+    }
+    #[test]
+    fn test_parse_datetime_works() {
+        assert_eq!(
+            parse_datetime("2024-01-05T18:00"),
+            Ok(NaiveDateTime::parse_from_str("202401051800", "%Y%m%d%H%M").unwrap())
+        );
+        assert!(parse_datetime("2024-01-05 18:00").is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "--since", "2024-01-05T18:00"]).is_err());
+    }
+    #[test]
+    fn test_read_area_works() {
+        assert_eq!(
+            read_area(&b"  osaka \nkyoto\n"[..], Lang::En).unwrap(),
+            "osaka"
+        );
+        assert_eq!(read_area(&b"bs"[..], Lang::En).unwrap(), "bs");
+        assert!(read_area(&b"\n"[..], Lang::En).is_err());
+        assert!(read_area(&b""[..], Lang::En).is_err());
+    }
+    #[test]
+    fn test_quiet_works() {
+        assert!(Opt::from_iter_safe(["tvnow", "-q", "-w", "-vv"]).is_ok());
+        assert!(Opt::from_iter_safe(["tvnow", "--quiet", "--today"]).is_ok());
+    }
+    #[test]
+    fn test_mode_works() {
+        let opt = Opt::from_iter_safe(["tvnow"]).unwrap();
+        assert_eq!(Mode::from(&opt), Mode::Current);
+        let opt = Opt::from_iter_safe(["tvnow", "-t"]).unwrap();
+        assert_eq!(Mode::from(&opt), Mode::Today);
+        let opt = Opt::from_iter_safe(["tvnow", "--week"]).unwrap();
+        assert_eq!(Mode::from(&opt), Mode::Week);
+        let opt = Opt::from_iter_safe(["tvnow", "--channels"]).unwrap();
+        assert_eq!(Mode::from(&opt), Mode::Channels);
+        let opt = Opt::from_iter_safe(["tvnow", "--list-channels", "bs"]).unwrap();
+        assert_eq!(Mode::from(&opt), Mode::Channels);
+    }
+    #[test]
+    fn test_page_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "-w", "--page", "2"]).unwrap();
+        assert_eq!(
+            Options::from(&opt).page,
+            Some(Page {
+                number: 2,
+                size: DEFAULT_PER_PAGE
+            })
+        );
+        let opt = Opt::from_iter_safe(["tvnow", "-w", "--per-page", "20"]).unwrap();
+        assert_eq!(
+            Options::from(&opt).page,
+            Some(Page {
+                number: 1,
+                size: 20
+            })
+        );
+        assert!(Opt::from_iter_safe(["tvnow", "-w", "--page", "0"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "--page", "2"]).is_err());
+    }
+    #[test]
+    fn test_truncate_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "-w", "--truncate", "40"]).unwrap();
+        assert_eq!(Options::from(&opt).truncate, Some(40));
+        let opt = Opt::from_iter_safe(["tvnow", "-w"]).unwrap();
+        assert_eq!(Options::from(&opt).truncate, None);
+        assert!(Opt::from_iter_safe(["tvnow", "--wrap", "--truncate", "40"]).is_err());
+    }
+    #[test]
+    fn test_verbose_works() {
+        let opt = Opt::from_iter_safe(["tvnow"]).unwrap();
+        assert_eq!(opt.verbose, 0);
+        let opt = Opt::from_iter_safe(["tvnow", "-vv"]).unwrap();
+        assert_eq!(opt.verbose, 2);
+    }
+    #[test]
+    fn test_guide_start_hour_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "-w"]).unwrap();
+        assert_eq!(Options::from(&opt).guide_start_hour, 5);
+        let opt = Opt::from_iter_safe(["tvnow", "-w", "--guide-start-hour", "4"]).unwrap();
+        assert_eq!(Options::from(&opt).guide_start_hour, 4);
+        assert!(Opt::from_iter_safe(["tvnow", "--guide-start-hour", "24"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "--guide-start-hour", "-1"]).is_err());
+    }
+    #[test]
+    fn test_day_offset_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "-t", "--yesterday"]).unwrap();
+        assert_eq!(Options::from(&opt).day_offset, -1);
+        let opt = Opt::from_iter_safe(["tvnow", "-t", "--tomorrow"]).unwrap();
+        assert_eq!(Options::from(&opt).day_offset, 1);
+        assert!(Opt::from_iter_safe(["tvnow", "--tomorrow"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "-t", "--yesterday", "--tomorrow"]).is_err());
+
+        let opt = Opt::from_iter_safe(["tvnow", "-t", "--relative-day", "-2"]).unwrap();
+        assert_eq!(Options::from(&opt).day_offset, -2);
+        let opt = Opt::from_iter_safe(["tvnow", "-t", "--relative-day", "3"]).unwrap();
+        assert_eq!(Options::from(&opt).day_offset, 3);
+        assert!(Opt::from_iter_safe(["tvnow", "-t", "--relative-day", "8"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "-t", "--relative-day", "x"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "--relative-day", "1"]).is_err());
+        assert!(Opt::from_iter_safe(["tvnow", "-t", "--relative-day", "1", "--tomorrow"]).is_err());
+    }
+    #[test]
+    fn test_search_works() {
+        let opt = Opt::from_iter_safe(["tvnow", "-t", "--search", "nhk"]).unwrap();
+        let search = Options::from(&opt).search.unwrap();
+        assert!(search.is_match("NHKニュース"));
+        let opt = Opt::from_iter_safe(["tvnow", "--search", "NHK", "--case-sensitive"]).unwrap();
+        let search = Options::from(&opt).search.unwrap();
+        assert!(!search.is_match("nhkニュース"));
+        let opt = Opt::from_iter_safe(["tvnow", "--search", "a.c"]).unwrap();
+        let search = Options::from(&opt).search.unwrap();
+        assert!(!search.is_match("abc"));
+        let opt = Opt::from_iter_safe(["tvnow", "--search-regex", r"第\d+話"]).unwrap();
+        let search = Options::from(&opt).search.unwrap();
+        assert!(search.is_match("ドラマ 第12話"));
+
+        let mut cli = Cli::new(vec![], vec![]);
+        let args = vec![
+            "tvnow".to_string(),
+            "--search-regex".to_string(),
+            "第(\\d+話".to_string(),
         ];
         let result = cli.execute(args.into_iter());
-        assert_eq!(result, ExitCode::Abnormal);
+        assert_eq!(result, ExitCode::Argument);
+    }
+    #[test]
+    fn test_exit_code_works() {
+        let e = anyhow::Error::new(TvnowError::Http("connection refused".to_string()));
+        assert_eq!(ExitCode::from(&e), ExitCode::Network);
+        let e = anyhow::Error::new(TvnowError::Offline("no such host".to_string()));
+        assert_eq!(ExitCode::from(&e), ExitCode::Network);
+        let e = anyhow::Error::new(TvnowError::BadStatus(503));
+        assert_eq!(ExitCode::from(&e), ExitCode::Network);
+        let e = anyhow::Error::new(TvnowError::Timeout);
+        assert_eq!(ExitCode::from(&e), ExitCode::Network);
+        let e = anyhow::Error::new(TvnowError::Parse(
+            "2 program columns for 1 channels".to_string(),
+        ));
+        assert_eq!(ExitCode::from(&e), ExitCode::Parse);
+        let e = anyhow::Error::new(TvnowError::Strict("no channels".to_string()));
+        assert_eq!(ExitCode::from(&e), ExitCode::Abnormal);
+        let e = Opt::from_iter_safe(["tvnow", "-1"]).unwrap_err();
+        assert_eq!(ExitCode::from(&anyhow::Error::new(e)), ExitCode::Argument);
+        let e = Opt::from_iter_safe(["tvnow", "--help"]).unwrap_err();
+        assert_eq!(ExitCode::from(&anyhow::Error::new(e)), ExitCode::Normal);
+        let e = anyhow::Error::new(TvnowError::UnknownArea("hogehoge".to_string()))
+            .context("hogehoge はエリアにありません");
+        assert_eq!(ExitCode::from(&e), ExitCode::Argument);
+        let e = anyhow::Error::new(TvnowError::UnknownChannel {
+            name: "hogehoge".to_string(),
+            message: Lang::Ja.unknown_channel("hogehoge"),
+        });
+        assert_eq!(ExitCode::from(&e), ExitCode::Argument);
+        let e = anyhow::Error::new(TvnowError::Interrupted);
+        assert_eq!(ExitCode::from(&e), ExitCode::Interrupted);
+        let e = anyhow::Error::new(io::Error::from(io::ErrorKind::BrokenPipe));
+        assert_eq!(ExitCode::from(&e), ExitCode::Abnormal);
+        let e = anyhow::anyhow!("unknown");
+        assert_eq!(ExitCode::from(&e), ExitCode::Abnormal);
     }
     #[test]
     #[ignore]
@@ -265,7 +2131,7 @@ mod tests {
         let mut cli = Cli::new(vec![], vec![]);
         let args = vec!["tvnow".to_string()];
         let result = cli.execute(args.into_iter());
-        assert_eq!(result, ExitCode::Abnormal);
+        assert_eq!(result, ExitCode::Argument);
         std::env::set_var(ENV_KEY, "tokyo");
     }
     #[test]
@@ -279,7 +2145,7 @@ mod tests {
         let mut cli = Cli::new(&mut out, &mut err);
         let args = vec!["tvnow".to_string()];
         let result = cli.execute(args.into_iter());
-        assert_eq!(result, ExitCode::Abnormal);
+        assert_eq!(result, ExitCode::Argument);
         let err_string = String::from_utf8(err).unwrap();
         assert_eq!(err_string, "fugafuga is not in the area\n");
         std::env::set_var(ENV_KEY, "tokyo");
@@ -301,7 +2167,7 @@ mod tests {
         // 東京のチャンネル番号
         let channels = ["1", "2", "4", "5", "6", "7", "8", "9", "9", "3", "3", "3"];
         for (i, s) in out_string.lines().enumerate() {
-            assert!(s.starts_with(channels[i]))
+            assert!(s.starts_with(&format!("[{}]", channels[i])))
         }
     }
 }
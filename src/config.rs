@@ -0,0 +1,86 @@
+use crate::theme::Theme;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// 設定ファイルの場所を上書きする環境変数
+const ENV_KEY: &str = "TVNOW_CONFIG";
+
+/// 設定ファイルの内容
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// `--channel`を指定しなかったときに表示するチャンネル
+    pub favorites: Vec<String>,
+    /// `--theme`を指定しなかったときの配色
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub theme: Option<Theme>,
+}
+
+impl Config {
+    /// 設定ファイルの場所
+    /// `TVNOW_CONFIG`、`$XDG_CONFIG_HOME/tvnow/config.json`、`~/.config/tvnow/config.json`の順
+    pub fn path() -> Option<PathBuf> {
+        if let Some(path) = env::var_os(ENV_KEY).filter(|p| !p.is_empty()) {
+            return Some(PathBuf::from(path));
+        }
+        let dir = env::var_os("XDG_CONFIG_HOME")
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| home_dir().map(|home| home.join(".config")))?;
+        Some(dir.join("tvnow").join("config.json"))
+    }
+
+    /// 設定ファイルを読み込む
+    /// ファイルが無ければ既定値
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// 設定ファイルに書き込む
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string_pretty(self)? + "\n")
+    }
+}
+
+pub(crate) fn home_dir() -> Option<PathBuf> {
+    env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .filter(|p| !p.is_empty())
+        .map(PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_config_round_trip_works() {
+        let dir = env::temp_dir().join(format!("tvnow-config-{}", std::process::id()));
+        let path = dir.join("tvnow").join("config.json");
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+
+        let config = Config {
+            favorites: vec!["NHK".to_string(), "フジ".to_string()],
+            theme: Some(Theme::Light),
+        };
+        config.save(&path).unwrap();
+        assert_eq!(Config::load(&path).unwrap(), config);
+
+        fs::write(&path, "{}").unwrap();
+        assert_eq!(Config::load(&path).unwrap(), Config::default());
+        fs::write(&path, "favorites").unwrap();
+        assert!(Config::load(&path).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
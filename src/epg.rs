@@ -1,402 +1,3916 @@
-use anyhow::{anyhow, Context, Result};
+use crate::clock::{Clock, SystemClock};
+use crate::lang::Lang;
+use crate::theme::Theme;
+use async_std::future::{self, FutureExt};
 use async_std::task;
 use chrono::prelude::*;
 use chrono::Duration;
-use colored::{Color, Colorize};
+use colored::Colorize;
+use crossterm::cursor::MoveToColumn;
+use crossterm::terminal::{Clear, ClearType};
+use crossterm::QueueableCommand;
+use encoding_rs::Encoding;
+use flate2::read::{GzDecoder, ZlibDecoder};
 use htmlize::unescape;
-use scraper::{Html, Selector};
-use std::io::{self, Write};
+use once_cell::sync::Lazy;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::{self, Read, Write};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
 
 const TV_GUIDE_START_TIME: u32 = 5;
-const TVCOLOR: Color = Color::BrightYellow;
-const BSCOLOR: Color = Color::BrightCyan;
+// `--week`で取得する日数
+const WEEK_COUNT: usize = 8;
+const BASE_URL: &str = "https://bangumi.org";
+// 番組表の時刻の協定世界時からのずれ(日本時間)
+const JST_OFFSET_HOURS: i64 = 9;
+const CHANNEL_SELECTOR: &str = "div#ch_area ul li.topmost p";
+const PROGRAM_AREA_SELECTOR: &str = "div#program_area ul";
+const PROGRAM_SELECTOR: &str = "li";
+const CURRENT_SELECTOR: &str = "li.sc-current";
+const FUTURE_SELECTOR: &str = "li.sc-future";
+const TITLE_SELECTOR: &str = "p.program_title";
+const DETAIL_SELECTOR: &str = "p.program_detail";
+// 番組表の番組から詳細ページへのリンク
+const DETAIL_LINK_SELECTOR: &str = "a[href]";
+// 詳細ページの概要
+const SYNOPSIS_SELECTOR: &str = r#"meta[property="og:description"], meta[name="description"]"#;
+// 端末の幅が分からないときの時間帯表の幅
+const GRID_DEFAULT_WIDTH: usize = 80;
+// `--week`で同時に取得するページ数
+const DEFAULT_CONCURRENCY: usize = 3;
+
+// bangumi.orgの応答を待つ時間
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+// `--week`の取得で一時的なエラーをやり直す回数
+const MAX_RETRIES: u32 = 2;
+// やり直すまでの待ち時間の基準(回数ごとに倍にする)
+const RETRY_BASE_DELAY: std::time::Duration = std::time::Duration::from_millis(500);
+// 中断されたかを確かめる間隔
+const INTERRUPT_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+// Ctrl-Cで取得を中断する
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+// `--week`の同じホストへの複数のリクエストで接続を使い回す
+static CLIENT: Lazy<surf::Client> = Lazy::new(surf::Client::new);
+// `--detail`で取得した詳細ページの概要(詳細ページのURLごと)
+static DETAILS: Lazy<Mutex<HashMap<String, String>>> = Lazy::new(Default::default);
+
+/// 番組表の取得・解析エラー
+#[derive(Debug, Clone, Error)]
+pub enum TvnowError {
+    /// bangumi.orgとの通信に失敗した
+    #[error("Failed to fetch from bangumi.org: {0}")]
+    Http(String),
+    /// ネットワークに繋がっていない(名前解決や接続ができない)
+    #[error("could not reach bangumi.org — are you online?")]
+    Offline(String),
+    /// bangumi.orgが成功以外のステータスを返した
+    #[error("bangumi.org returned HTTP status {0}")]
+    BadStatus(u16),
+    /// 番組表のHTMLを解釈できない
+    #[error("parse error: {0}")]
+    Parse(String),
+    /// チャンネルや番組列が一つも見つからない
+    #[error("unexpected page structure from bangumi.org — the site layout may have changed")]
+    Structure,
+    /// `--strict`指定時に番組表が想定と異なる
+    #[error("strict check failed: {0}")]
+    Strict(String),
+    /// 存在しないエリア名
+    #[error("unknown area: {0}")]
+    UnknownArea(String),
+    /// どのチャンネル名にも含まれない`--channel`
+    #[error("{message}")]
+    UnknownChannel { name: String, message: String },
+    /// bangumi.orgが応答しない
+    #[error("Timed out fetching from bangumi.org")]
+    Timeout,
+    /// レスポンスを展開・デコードできない
+    #[error("failed to decode response: {0}")]
+    Decode(String),
+    /// 組み込みのセレクタを解釈できない
+    #[error("internal selector error: {0}")]
+    Selector(String),
+    /// 取得中にCtrl-Cで中断された
+    #[error("Interrupted")]
+    Interrupted,
+}
+
+type Result<T, E = TvnowError> = std::result::Result<T, E>;
+
+impl TvnowError {
+    /// やり直せば成功するかもしれないエラーか
+    fn is_transient(&self) -> bool {
+        match self {
+            TvnowError::Http(_) | TvnowError::Timeout => true,
+            TvnowError::BadStatus(status) => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+}
+
+/// 番組表の解析に使うセレクタ
+struct Selectors {
+    channel: Selector,
+    program_area: Selector,
+    program: Selector,
+    current: Selector,
+    future: Selector,
+    title: Selector,
+    detail: Selector,
+    detail_link: Selector,
+    synopsis: Selector,
+}
+
+static SELECTORS: Lazy<Result<Selectors>> = Lazy::new(|| {
+    let parse =
+        |s: &str| Selector::parse(s).map_err(|e| TvnowError::Selector(format!("{}: {:?}", s, e)));
+    Ok(Selectors {
+        channel: parse(CHANNEL_SELECTOR)?,
+        program_area: parse(PROGRAM_AREA_SELECTOR)?,
+        program: parse(PROGRAM_SELECTOR)?,
+        current: parse(CURRENT_SELECTOR)?,
+        future: parse(FUTURE_SELECTOR)?,
+        title: parse(TITLE_SELECTOR)?,
+        detail: parse(DETAIL_SELECTOR)?,
+        detail_link: parse(DETAIL_LINK_SELECTOR)?,
+        synopsis: parse(SYNOPSIS_SELECTOR)?,
+    })
+});
+
+/// セレクタを検証する
+/// 番組表を表示する前に呼んでおけば表示中にパニックしない
+pub fn check_selectors() -> Result<()> {
+    SELECTORS.as_ref().map(|_| ()).map_err(Clone::clone)
+}
+
+fn selectors() -> &'static Selectors {
+    SELECTORS
+        .as_ref()
+        .expect("selectors are checked by check_selectors")
+}
+
+/// 表示オプション
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// 開始時刻を過ぎた番組を除外する
+    pub since_now: bool,
+    /// 現在放送中の番組を1行にまとめる
+    pub compact: bool,
+    /// タイトルに含まれていれば強調表示する語
+    pub highlight: Option<String>,
+    /// 放送日が切り替わる時刻(0〜23時)
+    pub guide_start_hour: u32,
+    /// 今日の番組表として表示する放送日のずれ(日)
+    pub day_offset: i64,
+    /// 番組の概要も表示する
+    pub detail: bool,
+    /// 同じタイトルで時間が連続する番組をまとめる
+    pub merge: bool,
+    /// 放送していないチャンネルを表示しない
+    pub hide_offair: bool,
+    /// チャンネルごとの番組数と放送時間の合計を表示する
+    pub summary: bool,
+    /// 表示言語
+    pub lang: Lang,
+    /// タイトルの検索条件
+    pub search: Option<Regex>,
+    /// 番組表が想定と異なればエラーにする
+    pub strict: bool,
+    /// `channel_names`がどのチャンネル名にも含まれなければエラーにする
+    pub check_channel_names: bool,
+    /// `--compact`と`--wrap`で折り返す幅(端末の桁数)
+    pub width: Option<usize>,
+    /// 長いタイトルを折り返す
+    pub wrap: bool,
+    /// 1行をこの桁数に収まるようにタイトルを切り詰める
+    pub truncate: Option<usize>,
+    /// `--today`と`--week`の並び順
+    pub sort: SortOrder,
+    /// 現在時刻の取得元
+    pub clock: Arc<dyn Clock>,
+    /// 出力形式
+    pub format: Format,
+    /// 1週間分の番組表の代わりに放送日ごとの時間帯の表を表示する
+    pub group_by_time: bool,
+    /// 表示するチャンネルのリモコン番号
+    pub channel_number: Option<u32>,
+    /// 表示するチャンネル名(部分一致)
+    pub channel_names: Vec<String>,
+    /// `--week`の表示するページ
+    pub page: Option<Page>,
+    /// チャンネルをリモコン番号順に並べる
+    pub sort_channels: bool,
+    /// `--week`の取得の進捗を標準エラー出力に表示する
+    pub progress: bool,
+    /// `--week`で同時に取得するページ数
+    pub concurrency: usize,
+    /// 番組表を取得するサイト(テスト用のモックサーバーなど)
+    pub base_url: String,
+    /// 1番組を1行に書き出す書式
+    pub template: Option<Template>,
+    /// `--week`でこの日時以降に始まる番組だけ
+    pub since: Option<NaiveDateTime>,
+    /// `--week`でこの日時より前に始まる番組だけ
+    pub until: Option<NaiveDateTime>,
+    /// `--today`でこの時刻以降に始まる番組だけ
+    pub start_after: Option<NaiveTime>,
+    /// 配色
+    pub theme: Theme,
+    /// ASCII文字だけで書き出し、色を付けない
+    pub ascii: bool,
+    /// `--week`を日付付きで1番組1行に書き出す
+    pub flat: bool,
+    /// 放送中の番組のうちこの分数以内に終わるものだけ
+    pub ending_within: Option<i64>,
+    /// 放送していないチャンネルに表示する文言
+    pub off_air_placeholder: Option<String>,
+    /// `--format`の日時を協定世界時で書き出す
+    pub utc: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            since_now: false,
+            compact: false,
+            highlight: None,
+            guide_start_hour: TV_GUIDE_START_TIME,
+            day_offset: 0,
+            detail: false,
+            merge: false,
+            hide_offair: false,
+            summary: false,
+            lang: Lang::default(),
+            search: None,
+            strict: false,
+            check_channel_names: false,
+            width: None,
+            wrap: false,
+            truncate: None,
+            sort: SortOrder::default(),
+            clock: Arc::new(SystemClock),
+            format: Format::default(),
+            group_by_time: false,
+            channel_number: None,
+            channel_names: vec![],
+            page: None,
+            sort_channels: false,
+            progress: false,
+            concurrency: DEFAULT_CONCURRENCY,
+            base_url: BASE_URL.to_string(),
+            template: None,
+            since: None,
+            until: None,
+            start_after: None,
+            theme: Theme::default(),
+            ascii: false,
+            flat: false,
+            ending_within: None,
+            off_air_placeholder: None,
+            utc: false,
+        }
+    }
+}
+
+impl Options {
+    /// 各プリンタの既定のテキストではなく、番組ごとに`--format`か`--template`で書き出すか
+    pub fn is_formatted(&self) -> bool {
+        self.format != Format::Text || self.template.is_some()
+    }
+
+    /// タイトルが検索条件に一致するか
+    /// 検索条件が無ければ常に一致する
+    fn is_match(&self, title: &str) -> bool {
+        self.search
+            .as_ref()
+            .map_or(true, |search| search.is_match(title))
+    }
+
+    /// 番組が`since`と`until`の間に始まるか
+    /// 開始時刻が分からない番組はどちらかの指定があれば除く
+    fn in_period(&self, program: &Program) -> bool {
+        if self.since.is_none() && self.until.is_none() {
+            return true;
+        }
+        match program.start {
+            Some(start) => {
+                self.since.map_or(true, |since| since <= start)
+                    && self.until.map_or(true, |until| start < until)
+            }
+            None => false,
+        }
+    }
+
+    /// チャンネルを表示するか
+    /// リモコン番号の指定があれば一致するチャンネルだけ
+    /// チャンネル名の指定があればどれかを含むチャンネルだけ(大文字小文字は区別しない)
+    fn shows_channel(&self, channel: &Channel) -> bool {
+        let number = match self.channel_number {
+            Some(number) => channel.remote_number() == Some(number),
+            None => true,
+        };
+        let name = channel.name.to_lowercase();
+        number
+            && (self.channel_names.is_empty()
+                || self
+                    .channel_names
+                    .iter()
+                    .any(|n| name.contains(&n.to_lowercase())))
+    }
+
+    /// 放送していないチャンネルに表示する文言
+    /// 指定がなければ表示言語の文言
+    pub fn off_air(&self) -> &str {
+        self.off_air_placeholder
+            .as_deref()
+            .unwrap_or_else(|| self.lang.off_air())
+    }
+
+    /// 開始時刻と終了時刻の区切り
+    pub fn separator(&self) -> &'static str {
+        if self.ascii {
+            "->"
+        } else {
+            "~"
+        }
+    }
+
+    /// 切り詰めたタイトルの末尾
+    fn ellipsis(&self) -> &'static str {
+        if self.ascii {
+            "..."
+        } else {
+            "…"
+        }
+    }
+
+    /// `indent`桁目から始まるタイトルを`--wrap`なら折り返し、`--truncate`なら切り詰める
+    /// 幅が分からなければそのまま
+    fn fit(&self, indent: usize, title: String) -> String {
+        match (self.truncate, self.width) {
+            (Some(cols), _) => truncate_text(&title, indent, cols, self.ellipsis()),
+            (None, Some(width)) if self.wrap => wrap_text(&title, indent, width),
+            _ => title,
+        }
+    }
+}
 
 pub trait Printer<T: Write> {
-    fn print(&self, w: T);
+    /// 番組表を書き出して、書き出した番組数(`--channels`ではチャンネル数)を返す
+    /// 書き込みに失敗すればそのエラー
+    fn print(&self, w: T) -> io::Result<usize>;
+}
+
+/// `--today`と`--week`の並び順
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    /// チャンネルごと
+    #[default]
+    Channel,
+    /// 全チャンネルを開始時刻順
+    Time,
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "channel" => Ok(SortOrder::Channel),
+            "time" => Ok(SortOrder::Time),
+            _ => Err(format!("{} is not a sort order (channel, time)", s)),
+        }
+    }
+}
+
+/// 出力形式
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Format {
+    #[default]
+    Text,
+    Json,
+    Csv,
+    Markdown,
+    Ical,
+    Tsv,
+    Ndjson,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "markdown" => Ok(Format::Markdown),
+            "ical" => Ok(Format::Ical),
+            "tsv" => Ok(Format::Tsv),
+            "ndjson" => Ok(Format::Ndjson),
+            _ => Err(format!(
+                "{} is not a format (text, json, csv, markdown, ical, tsv, ndjson)",
+                s
+            )),
+        }
+    }
+}
+
+/// `--template`の書式
+/// `{channel}`などの差し込み項目とそのままの文字列を並べたもの
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template(Vec<Segment>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Text(String),
+    Field(Field),
+}
+
+/// 差し込み項目
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Channel,
+    /// リモコン番号
+    Number,
+    Date,
+    Start,
+    End,
+    /// 放送時間(`1:30`)
+    Duration,
+    Title,
+    Detail,
+}
+
+impl FromStr for Field {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "channel" => Ok(Field::Channel),
+            "number" => Ok(Field::Number),
+            "date" => Ok(Field::Date),
+            "start" => Ok(Field::Start),
+            "end" => Ok(Field::End),
+            "duration" => Ok(Field::Duration),
+            "title" => Ok(Field::Title),
+            "detail" => Ok(Field::Detail),
+            _ => Err(format!(
+                "{{{}}} is not a placeholder (channel, number, date, start, end, duration, title, detail)",
+                s
+            )),
+        }
+    }
+}
+
+impl Template {
+    /// 番組の説明を使うか
+    pub fn needs_detail(&self) -> bool {
+        self.0.contains(&Segment::Field(Field::Detail))
+    }
+
+    fn render(&self, program: &Program) -> String {
+        self.0
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Field(Field::Channel) => program.name.clone(),
+                Segment::Field(Field::Number) => program.number.clone().unwrap_or_default(),
+                Segment::Field(Field::Date) => format_time(program.start, "%Y-%m-%d"),
+                Segment::Field(Field::Start) => format_time(program.start, "%R"),
+                Segment::Field(Field::End) => format_time(program.end, "%R"),
+                Segment::Field(Field::Duration) => program
+                    .duration()
+                    .map(|d| format!("{}:{:02}", d.num_hours(), d.num_minutes() % 60))
+                    .unwrap_or_default(),
+                Segment::Field(Field::Title) => program.title.clone(),
+                Segment::Field(Field::Detail) => program.detail.clone().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
+/// `{{`と`}}`はそれぞれ`{`と`}`
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut segments = vec![];
+        let mut text = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    text.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    text.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(format!("unclosed {{ in {}", s)),
+                        }
+                    }
+                    if !text.is_empty() {
+                        segments.push(Segment::Text(std::mem::take(&mut text)));
+                    }
+                    segments.push(Segment::Field(name.parse()?));
+                }
+                '}' => return Err(format!("unmatched }} in {}", s)),
+                c => text.push(c),
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Template(segments))
+    }
+}
+
+/// `--week`のページ分け
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Page {
+    /// 1から始まるページ番号
+    pub number: usize,
+    /// 1ページの番組数
+    pub size: usize,
+}
+
+/// 番組表の取得元
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelSource {
+    /// 地上波(地域ID)
+    Area(u8),
+    /// BS放送
+    Bs,
+    /// CS放送
+    Cs,
+}
+
+impl ChannelSource {
+    /// `base_url`のサイトの番組表のURL
+    /// `date`を指定するとその放送日の番組表
+    fn url(&self, base_url: &str, date: Option<NaiveDate>) -> String {
+        let path = match self {
+            ChannelSource::Area(_) => "epg/td",
+            ChannelSource::Bs => "epg/bs",
+            ChannelSource::Cs => "epg/cs",
+        };
+        let base = format!("{}/{}", base_url.trim_end_matches('/'), path);
+        let mut params = vec![];
+        if let Some(date) = date {
+            params.push(format!("broad_cast_date={}", date.format("%Y%m%d")));
+        }
+        if let ChannelSource::Area(id) = self {
+            params.push(format!("ggm_group_id={}", id));
+        }
+        if params.is_empty() {
+            base.to_string()
+        } else {
+            format!("{}?{}", base, params.join("&"))
+        }
+    }
 }
 
 pub struct Tv {
+    source: ChannelSource,
     epg_doc: Html,
+    options: Options,
 }
 
 impl Tv {
-    pub fn init<T: Write>(id: u8) -> Result<Box<dyn Printer<T>>> {
-        let url = format!("https://bangumi.org/epg/td?ggm_group_id={}", id);
-        let html = get_html(&url)?;
-        let printer = Box::new(Tv { epg_doc: html });
+    /// 取得する番組表のURL
+    pub fn urls(source: ChannelSource, options: &Options) -> Vec<String> {
+        vec![source.url(&options.base_url, None)]
+    }
+
+    pub fn init<T: Write>(source: ChannelSource, options: &Options) -> Result<Box<dyn Printer<T>>> {
+        let body = get_body(&Self::urls(source, options)[0])?;
+        Self::from_html(source, &body, options)
+    }
+
+    /// 取得済みの番組表のHTMLから作る
+    pub fn from_html<T: Write>(
+        source: ChannelSource,
+        html: &str,
+        options: &Options,
+    ) -> Result<Box<dyn Printer<T>>> {
+        let html = parse_document(html)?;
+        check_strict(&html, options)?;
+        check_channel_names(&html, options)?;
+        prefetch_details(&html, &selectors().current, options);
+        let printer = Box::new(Tv {
+            source,
+            epg_doc: html,
+            options: options.clone(),
+        });
 
         Ok(printer)
     }
 }
 
 impl<T: Write> Printer<T> for Tv {
-    fn print(&self, w: T) {
-        let ch_selector = Selector::parse("div#ch_area ul li.topmost p").unwrap();
-        let channels = self
-            .epg_doc
-            .select(&ch_selector)
-            .map(|e| e.inner_html())
-            .collect::<Vec<_>>();
-        let channels = channels.iter().map(|s| s.trim()).collect::<Vec<_>>();
+    fn print(&self, w: T) -> io::Result<usize> {
+        let now = self.options.clock.now().naive_local();
+        let mut columns = channel_columns(&self.epg_doc, &self.options);
+        if let Some(minutes) = self.options.ending_within {
+            columns = ending_columns(columns, now, minutes);
+        }
+        let color = self.options.theme.channel(self.source);
 
-        let program_selector = Selector::parse("div#program_area ul").unwrap();
-        let current_selector = Selector::parse("li.sc-current").unwrap();
-        let title_selector = Selector::parse("p.program_title").unwrap();
+        let current_selector = &selectors().current;
+        let title_selector = &selectors().title;
 
-        let program_area = self.epg_doc.select(&program_selector);
         let mut buf = io::BufWriter::new(w);
-        for (i, ul) in program_area.enumerate() {
-            match ul.select(&current_selector).next() {
+        if self.options.is_formatted() {
+            let programs = current_programs(&columns, &self.options);
+            write_programs_as(&mut buf, &programs, &self.options)?;
+            buf.flush()?;
+            return Ok(programs.len());
+        }
+        let mut count = 0;
+        let mut compact = vec![];
+        for column in &columns {
+            match column.ul.select(current_selector).next() {
                 Some(current) => {
-                    if let Some(title) = current.select(&title_selector).next() {
-                        writeln!(
-                            buf,
-                            "{} {}",
-                            channels[i].color(TVCOLOR),
-                            unescape(title.inner_html())
-                        )
-                        .unwrap();
+                    if let Some(title) =
+                        program_title(current, title_selector).filter(|t| self.options.is_match(t))
+                    {
+                        count += 1;
+                        let plain_title_width = title.width();
+                        let title = highlight(title, &self.options);
+                        if self.options.compact {
+                            let name = &column.channel.name;
+                            let width = name.width() + 1 + plain_title_width;
+                            compact.push((width, format!("{}:{}", name.color(color), title)));
+                        } else {
+                            let label = current
+                                .value()
+                                .attr("e")
+                                .and_then(|e| remaining_minutes(e, now))
+                                .map(|minutes| self.options.lang.remaining(minutes));
+                            let title = match label {
+                                Some(label) => format!("{} {}", title, label),
+                                None => title,
+                            };
+                            let title = self.options.fit(column.label.width() + 1, title);
+                            writeln!(buf, "{} {}", column.label.color(color), title)?;
+                            if let Some(detail) = program_detail(current, &self.options) {
+                                writeln!(buf, "    {}", detail)?;
+                            }
+                        }
                     }
                 }
-                None => writeln!(buf, "{} 現在放送していません", channels[i]).unwrap(),
+                None if self.options.hide_offair || self.options.search.is_some() => {}
+                None if self.options.compact => {
+                    let placeholder = self.options.off_air_placeholder.as_deref().unwrap_or("-");
+                    compact.push((
+                        column.channel.name.width() + 1 + placeholder.width(),
+                        format!("{}:{}", column.channel.name, placeholder),
+                    ))
+                }
+                None => match self.options.off_air() {
+                    "" => writeln!(buf, "{}", column.label)?,
+                    off_air => writeln!(buf, "{} {}", column.label, off_air)?,
+                },
+            }
+        }
+        if self.options.compact {
+            for line in wrap_compact(&compact, self.options.width) {
+                writeln!(buf, "{}", line)?;
             }
         }
+        buf.flush()?;
+        Ok(count)
     }
 }
 
 pub struct TodayTv {
+    source: ChannelSource,
     epg_doc: Html,
+    options: Options,
 }
 
 impl TodayTv {
-    pub fn init<T: Write>(id: u8) -> Result<Box<dyn Printer<T>>> {
-        let url = format!("https://bangumi.org/epg/td?ggm_group_id={}", id);
-        let html = get_html(&url)?;
-        let printer = Box::new(TodayTv { epg_doc: html });
+    /// 表示する放送日
+    pub fn date(options: &Options) -> NaiveDate {
+        broadcast_date(options.clock.now(), options.guide_start_hour)
+            + Duration::days(options.day_offset)
+    }
+
+    /// 放送日の終わり(翌日の`guide_start_hour`時)
+    fn day_end(options: &Options) -> NaiveDateTime {
+        (Self::date(options) + Duration::days(1))
+            .and_hms_opt(options.guide_start_hour, 0, 0)
+            .expect("guide start hour is between 0 and 23")
+    }
+
+    /// `--start-after`の時刻を放送日の日時にする
+    /// 放送日が切り替わる時刻より前は翌日の深夜
+    fn start_after(options: &Options) -> Option<NaiveDateTime> {
+        let time = options.start_after?;
+        let date = Self::date(options);
+        if time.hour() < options.guide_start_hour {
+            Some((date + Duration::days(1)).and_time(time))
+        } else {
+            Some(date.and_time(time))
+        }
+    }
+
+    /// 取得する番組表のURL
+    pub fn urls(source: ChannelSource, options: &Options) -> Vec<String> {
+        // 放送日が暦の日付と異なる深夜や前後の日は放送日を指定する
+        let date = Self::date(options);
+        if date == options.clock.now().date_naive() {
+            vec![source.url(&options.base_url, None)]
+        } else {
+            vec![source.url(&options.base_url, Some(date))]
+        }
+    }
+
+    pub fn init<T: Write>(source: ChannelSource, options: &Options) -> Result<Box<dyn Printer<T>>> {
+        let body = get_body(&Self::urls(source, options)[0])?;
+        Self::from_html(source, &body, options)
+    }
+
+    /// 取得済みの番組表のHTMLから作る
+    pub fn from_html<T: Write>(
+        source: ChannelSource,
+        html: &str,
+        options: &Options,
+    ) -> Result<Box<dyn Printer<T>>> {
+        let html = parse_document(html)?;
+        check_strict(&html, options)?;
+        check_channel_names(&html, options)?;
+        prefetch_details(&html, &selectors().future, options);
+        let printer = Box::new(TodayTv {
+            source,
+            epg_doc: html,
+            options: options.clone(),
+        });
 
         Ok(printer)
     }
 }
 
 impl<T: Write> Printer<T> for TodayTv {
-    fn print(&self, w: T) {
-        let ch_selector = Selector::parse("div#ch_area ul li.topmost p").unwrap();
-        let channels = self
-            .epg_doc
-            .select(&ch_selector)
-            .map(|e| e.inner_html())
-            .collect::<Vec<_>>();
-        let channels = channels.iter().map(|s| s.trim()).collect::<Vec<_>>();
+    fn print(&self, w: T) -> io::Result<usize> {
+        let color = self.options.theme.channel(self.source);
 
-        let program_selector = Selector::parse("div#program_area ul").unwrap();
-        let future_selector = Selector::parse("li.sc-future").unwrap();
-        let title_selector = Selector::parse("p.program_title").unwrap();
-
-        let program_area = self.epg_doc.select(&program_selector);
         let mut buf = io::BufWriter::new(w);
-        for (i, ul) in program_area.enumerate() {
-            writeln!(buf, "{}", channels[i].color(TVCOLOR)).unwrap();
-            for li in ul.select(&future_selector) {
-                let start = li.value().attr("s").unwrap();
-                let start_hours = start.get(8..10).unwrap();
-                let start_minutes = start.get(10..12).unwrap();
-                let end = li.value().attr("e").unwrap();
-                let end_hours = end.get(8..10).unwrap();
-                let end_minutes = end.get(10..12).unwrap();
-                if let Some(title) = li.select(&title_selector).next() {
-                    writeln!(
-                        buf,
-                        "{}:{} ~ {}:{} {}",
-                        start_hours,
-                        start_minutes,
-                        end_hours,
-                        end_minutes,
-                        unescape(title.inner_html())
-                    )
-                    .unwrap();
+        let mut summary = Summary::default();
+        let mut channels = vec![];
+        let mut columns = vec![];
+        // 深夜番組は含め、翌朝の番組は含めない
+        let day_end = Self::day_end(&self.options);
+        let start_after = Self::start_after(&self.options);
+        for column in channel_columns(&self.epg_doc, &self.options) {
+            summary.entry(&column.label);
+            let mut programs = parse_programs(&column, &self.options);
+            programs.retain(|program| program.start.map_or(true, |start| start < day_end));
+            if let Some(after) = start_after {
+                programs.retain(|program| program.start.map_or(false, |start| after <= start));
+            }
+            channels.push(column.label);
+            for program in &programs {
+                summary.add(&program.channel, program.duration());
+            }
+            columns.push(programs);
+        }
+        let count = columns.iter().map(|programs| programs.len()).sum();
+        if self.options.is_formatted() {
+            let mut programs = columns.into_iter().flatten().collect::<Vec<_>>();
+            if self.options.sort == SortOrder::Time {
+                sort_programs(&mut programs);
+            }
+            write_programs_as(&mut buf, &programs, &self.options)?;
+            buf.flush()?;
+            return Ok(count);
+        }
+        let date = Self::date(&self.options);
+        let write_program =
+            |buf: &mut io::BufWriter<T>, indent: usize, program: &Program| -> io::Result<()> {
+                let time = format!(
+                    "{} {} {} ",
+                    guide_time(program.start, date),
+                    self.options.separator(),
+                    guide_time(program.end, date)
+                );
+                let title = highlight(program.title.clone(), &self.options);
+                let title = self.options.fit(indent + time.width(), title);
+                writeln!(buf, "{}{}", time, title)?;
+                if let Some(detail) = &program.detail {
+                    writeln!(buf, "    {}", detail)?;
+                }
+                Ok(())
+            };
+        match self.options.sort {
+            SortOrder::Channel => {
+                for (channel, programs) in channels.iter().zip(&columns) {
+                    writeln!(buf, "{}", channel.color(color))?;
+                    for program in programs {
+                        write_program(&mut buf, 0, program)?;
+                    }
+                }
+            }
+            SortOrder::Time => {
+                let mut programs = columns.into_iter().flatten().collect::<Vec<_>>();
+                sort_programs(&mut programs);
+                for program in &programs {
+                    write!(buf, "{} ", program.channel.color(color))?;
+                    write_program(&mut buf, program.channel.width() + 1, program)?;
                 }
             }
         }
+        if self.options.summary {
+            summary.write(&mut buf, self.options.lang)?;
+        }
+        buf.flush()?;
+        Ok(count)
     }
 }
 
-pub struct WeekTv {
-    epg_docs: Vec<Html>,
+pub struct ChannelList {
+    source: ChannelSource,
+    epg_doc: Html,
+    options: Options,
 }
 
-impl WeekTv {
-    pub fn init<T: Write>(id: u8) -> Result<Box<dyn Printer<T>>> {
-        let mut datetime = Local::now();
-        if datetime.hour() < TV_GUIDE_START_TIME {
-            datetime = Local::now() + Duration::days(-1);
-        }
-        const WEEK_COUNT: usize = 8;
-        let mut urls: [String; WEEK_COUNT] = Default::default();
-        for index in urls.iter_mut().take(WEEK_COUNT) {
-            let today = datetime.format("%Y%m%d");
-            let url = format!(
-                "https://bangumi.org/epg/td?broad_cast_date={}&ggm_group_id={}",
-                today, id
-            );
-            *index = url;
-            datetime += Duration::days(1);
-        }
-        let htmls = async_get_htmls(urls.to_vec())?;
-        let printer = Box::new(WeekTv { epg_docs: htmls });
+impl ChannelList {
+    /// 取得する番組表のURL
+    pub fn urls(source: ChannelSource, options: &Options) -> Vec<String> {
+        Tv::urls(source, options)
+    }
+
+    pub fn init<T: Write>(source: ChannelSource, options: &Options) -> Result<Box<dyn Printer<T>>> {
+        let body = get_body(&Self::urls(source, options)[0])?;
+        Self::from_html(source, &body, options)
+    }
+
+    /// 取得済みの番組表のHTMLから作る
+    pub fn from_html<T: Write>(
+        source: ChannelSource,
+        html: &str,
+        options: &Options,
+    ) -> Result<Box<dyn Printer<T>>> {
+        let html = parse_document(html)?;
+        check_strict(&html, options)?;
+        check_channel_names(&html, options)?;
+        let printer = Box::new(ChannelList {
+            source,
+            epg_doc: html,
+            options: options.clone(),
+        });
 
         Ok(printer)
     }
 }
 
-impl<T: Write> Printer<T> for WeekTv {
-    fn print(&self, w: T) {
+impl<T: Write> Printer<T> for ChannelList {
+    fn print(&self, w: T) -> io::Result<usize> {
+        let color = self.options.theme.channel(self.source);
         let mut buf = io::BufWriter::new(w);
-        for epg_doc in &self.epg_docs {
-            let ch_selector = Selector::parse("div#ch_area ul li.topmost p").unwrap();
-            let channels = epg_doc
-                .select(&ch_selector)
-                .map(|e| e.inner_html())
-                .collect::<Vec<_>>();
-            let channels = channels.iter().map(|s| s.trim()).collect::<Vec<_>>();
-
-            let program_selector = Selector::parse("div#program_area ul").unwrap();
-            let future_selector = Selector::parse("li.sc-future").unwrap();
-            let title_selector = Selector::parse("p.program_title").unwrap();
-
-            let program_area = epg_doc.select(&program_selector);
-            for (i, ul) in program_area.enumerate() {
-                for li in ul.select(&future_selector) {
-                    let start = li.value().attr("s").unwrap();
-                    let end = li.value().attr("e").unwrap();
-                    let start = NaiveDateTime::parse_from_str(start, "%Y%m%d%H%M").unwrap();
-                    let end = NaiveDateTime::parse_from_str(end, "%Y%m%d%H%M").unwrap();
-
-                    if let Some(title) = li.select(&title_selector).next() {
-                        writeln!(
-                            buf,
-                            "{} {} ~ {} {}",
-                            channels[i],
-                            start.format("%a %R"),
-                            end.format("%a %R"),
-                            unescape(title.inner_html())
-                        )
-                        .unwrap();
-                    }
-                }
-            }
+        let channels = parse_channels(&self.epg_doc);
+        let mut rows: Vec<_> = channels
+            .iter()
+            .zip(channel_labels(&channels))
+            .filter(|(channel, _)| self.options.shows_channel(channel))
+            .collect();
+        if self.options.sort_channels {
+            rows.sort_by_key(|(channel, _)| channel.sort_key());
         }
+        for (_, label) in &rows {
+            writeln!(buf, "{}", label.color(color))?;
+        }
+        buf.flush()?;
+        Ok(rows.len())
     }
 }
 
-pub struct BsTv {
-    epg_doc: Html,
+pub struct WeekTv {
+    /// 放送日とその日の番組表
+    days: Vec<(NaiveDate, Day)>,
+    options: Options,
 }
 
-impl BsTv {
-    pub fn init<T: Write>() -> Result<Box<dyn Printer<T>>> {
-        let url = "https://bangumi.org/epg/bs";
-        let html = get_html(url)?;
-
-        let printer = Box::new(BsTv { epg_doc: html });
+/// 解析済みの1日分の番組表
+#[derive(Debug, Clone, PartialEq)]
+struct Day {
+    /// チャンネルの表示名とその番組
+    columns: Vec<(String, Vec<Program>)>,
+    /// 番組が一つでも載っているか
+    has_programs: bool,
+}
 
-        Ok(printer)
+impl Day {
+    fn parse(html: &str, options: &Options) -> Result<Self> {
+        let doc = parse_document(html)?;
+        check_strict(&doc, options)?;
+        check_channel_names(&doc, options)?;
+        prefetch_details(&doc, &selectors().future, options);
+        let columns = channel_columns(&doc, options)
+            .into_iter()
+            .map(|column| {
+                let programs = parse_programs(&column, options);
+                (column.label, programs)
+            })
+            .collect();
+        Ok(Day {
+            columns,
+            has_programs: has_programs(&doc),
+        })
     }
 }
 
-impl<T: Write> Printer<T> for BsTv {
-    fn print(&self, w: T) {
-        let ch_selector = Selector::parse("div#ch_area ul li.topmost p").unwrap();
-        let channels = self
-            .epg_doc
-            .select(&ch_selector)
-            .map(|e| e.inner_html())
+/// 1日ごとの番組表のHTMLをスレッドごとに並列で解析する
+/// `Html`はスレッド間で受け渡せないので、番組まで解析してから返す
+/// 結果は元の日の順
+fn parse_days<S: AsRef<str> + Sync>(htmls: &[S], options: &Options) -> Vec<Result<Day>> {
+    thread::scope(|scope| {
+        let handles = htmls
+            .iter()
+            .map(|html| scope.spawn(move || Day::parse(html.as_ref(), options)))
             .collect::<Vec<_>>();
-        let channels = channels.iter().map(|s| s.trim()).collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("parser thread panicked"))
+            .collect()
+    })
+}
+
+impl WeekTv {
+    /// 表示する最初と最後の放送日
+    pub fn dates(options: &Options) -> (NaiveDate, NaiveDate) {
+        let first = broadcast_date(options.clock.now(), options.guide_start_hour);
+        (first, first + Duration::days(WEEK_COUNT as i64 - 1))
+    }
+
+    /// 取得する番組表のURL
+    pub fn urls(source: ChannelSource, options: &Options) -> Vec<String> {
+        let mut datetime = Self::dates(options).0;
+        let mut urls: [String; WEEK_COUNT] = Default::default();
+        for index in urls.iter_mut().take(WEEK_COUNT) {
+            *index = source.url(&options.base_url, Some(datetime));
+            datetime += Duration::days(1);
+        }
+        urls.to_vec()
+    }
+
+    pub fn init<T: Write>(source: ChannelSource, options: &Options) -> Result<Box<dyn Printer<T>>> {
+        let bodies = async_get_bodies(Self::urls(source, options), options)?;
+        Self::from_htmls(&bodies, options)
+    }
+
+    /// 取得済みの1日ごとの番組表のHTMLから作る
+    pub fn from_htmls<T: Write, S: AsRef<str> + Sync>(
+        htmls: &[S],
+        options: &Options,
+    ) -> Result<Box<dyn Printer<T>>> {
+        Ok(Box::new(Self::new(htmls, options)?))
+    }
 
-        let program_selector = Selector::parse("div#program_area ul").unwrap();
-        let current_selector = Selector::parse("li.sc-current").unwrap();
-        let title_selector = Selector::parse("p.program_title").unwrap();
+    fn new<S: AsRef<str> + Sync>(htmls: &[S], options: &Options) -> Result<Self> {
+        let started = Instant::now();
+        let first = Self::dates(options).0;
+        let mut days = vec![];
+        for (index, day) in parse_days(htmls, options).into_iter().enumerate() {
+            let day = day?;
+            // bangumi.orgが提供していない先の日は番組の無いページになる
+            if index > 0 && !day.has_programs {
+                log::warn!(
+                    "bangumi.org has no programs for {}; programs are available up to {}",
+                    first + Duration::days(index as i64),
+                    first + Duration::days(days.len() as i64 - 1)
+                );
+                continue;
+            }
+            days.push((first + Duration::days(index as i64), day));
+        }
+        if days.is_empty() {
+            return Err(TvnowError::Structure);
+        }
+        log::info!("parsed {} pages in {:?}", days.len(), started.elapsed());
+        Ok(WeekTv {
+            days,
+            options: options.clone(),
+        })
+    }
+}
 
-        let program_area = self.epg_doc.select(&program_selector);
+impl<T: Write> Printer<T> for WeekTv {
+    fn print(&self, w: T) -> io::Result<usize> {
         let mut buf = io::BufWriter::new(w);
-        for (i, ul) in program_area.enumerate() {
-            match ul.select(&current_selector).next() {
-                Some(current) => {
-                    if let Some(title) = current.select(&title_selector).next() {
-                        writeln!(
-                            buf,
-                            "{} {}",
-                            channels[i].color(BSCOLOR),
-                            unescape(title.inner_html())
+        if self.options.group_by_time {
+            // 放送日ごとに表にする
+            // `--since`と`--until`で番組が無くなった日は表にしない
+            let width = self.options.width.unwrap_or(GRID_DEFAULT_WIDTH);
+            let has_period = self.options.since.is_some() || self.options.until.is_some();
+            let mut count = 0;
+            let mut printed = false;
+            let mut seen = HashSet::new();
+            for (date, day) in &self.days {
+                let channels = day
+                    .columns
+                    .iter()
+                    .map(|(label, _)| label.clone())
+                    .collect::<Vec<_>>();
+                let programs = day
+                    .columns
+                    .iter()
+                    .flat_map(|(_, programs)| programs.iter().cloned())
+                    .filter(|program| {
+                        self.options.in_period(program)
+                            && program
+                                .start
+                                .map_or(true, |start| seen.insert((program.channel.clone(), start)))
+                    })
+                    .collect::<Vec<_>>();
+                if has_period && programs.is_empty() {
+                    continue;
+                }
+                if printed {
+                    writeln!(buf)?;
+                }
+                printed = true;
+                writeln!(
+                    buf,
+                    "{}({})",
+                    date.format("%Y-%m-%d"),
+                    self.options.lang.weekday(date.weekday())
+                )?;
+                for line in time_grid(&channels, &programs, width, self.options.ellipsis()) {
+                    writeln!(buf, "{}", line)?;
+                }
+                count += programs.len();
+            }
+            buf.flush()?;
+            return Ok(count);
+        }
+        let mut count = 0;
+        let mut summary = Summary::default();
+        let write_programs =
+            |buf: &mut io::BufWriter<T>, programs: Vec<Program>| -> io::Result<()> {
+                for program in programs {
+                    let prefix = if self.options.flat {
+                        format!(
+                            "{} {} ",
+                            flat_time(&program, self.options.lang),
+                            program.channel
                         )
-                        .unwrap();
+                    } else {
+                        format!(
+                            "{} {} {} {} ",
+                            program.channel,
+                            format_time(program.start, "%a %R"),
+                            self.options.separator(),
+                            format_time(program.end, "%a %R")
+                        )
+                    };
+                    let title = highlight(program.title, &self.options);
+                    let title = self.options.fit(prefix.width(), title);
+                    writeln!(buf, "{}{}", prefix, title)?;
+                    if let Some(detail) = program.detail {
+                        writeln!(buf, "    {}", detail)?;
                     }
                 }
-                None => writeln!(buf, "{} 現在放送していません", channels[i]).unwrap(),
+                Ok(())
+            };
+        let mut week = vec![];
+        // 放送日の境目の番組は前後の日の番組表の両方に載ることがある
+        let mut seen = HashSet::new();
+        for (index, (_, page)) in self.days.iter().enumerate() {
+            let mut day = vec![];
+            for (label, programs) in &page.columns {
+                summary.entry(label);
+                let mut programs = programs.clone();
+                if self.options.merge {
+                    programs = merge_programs(programs);
+                }
+                programs.retain(|program| {
+                    self.options.in_period(program)
+                        && program
+                            .start
+                            .map_or(true, |start| seen.insert((program.channel.clone(), start)))
+                });
+                for program in &programs {
+                    summary.add(&program.channel, program.duration());
+                }
+                day.extend(programs);
+            }
+            log::debug!("page {}: {} programs to print", index + 1, day.len());
+            match (
+                self.options.sort,
+                self.options.is_formatted(),
+                self.options.page,
+            ) {
+                // 1日分ずつ書き出して端末に少しずつ表示されるようにする
+                (SortOrder::Channel, false, None) => {
+                    count += day.len();
+                    write_programs(&mut buf, day)?;
+                    buf.flush()?;
+                }
+                // 1行に1番組のJSONも1日分ずつ流せる
+                (SortOrder::Channel, true, None)
+                    if self.options.format == Format::Ndjson && self.options.template.is_none() =>
+                {
+                    count += day.len();
+                    write_programs_as(&mut buf, &day, &self.options)?;
+                }
+                // 時刻順やテキスト以外、ページ分けは全日分が揃うまで出力できない
+                _ => week.extend(day),
             }
         }
+        if self.options.sort == SortOrder::Time {
+            sort_programs(&mut week);
+        }
+        let mut footer = None;
+        if let Some(page) = self.options.page {
+            let (programs, pages) = paginate(week, page);
+            week = programs;
+            footer = Some(self.options.lang.page(page.number, pages));
+        }
+        count += week.len();
+        if self.options.is_formatted() {
+            write_programs_as(&mut buf, &week, &self.options)?;
+            buf.flush()?;
+            return Ok(count);
+        }
+        write_programs(&mut buf, week)?;
+        if let Some(footer) = footer {
+            writeln!(buf, "{}", footer)?;
+        }
+        if self.options.summary {
+            summary.write(&mut buf, self.options.lang)?;
+        }
+        buf.flush()?;
+        Ok(count)
     }
 }
 
-pub struct TodayBsTv {
-    epg_doc: Html,
+/// チャンネルごとの番組数と放送時間の合計
+#[derive(Default)]
+struct Summary {
+    channels: Vec<(String, usize, Duration)>,
 }
 
-impl TodayBsTv {
-    pub fn init<T: Write>() -> Result<Box<dyn Printer<T>>> {
-        let url = "https://bangumi.org/epg/bs";
-        let html = get_html(url)?;
+impl Summary {
+    fn entry(&mut self, channel: &str) -> &mut (String, usize, Duration) {
+        match self.channels.iter().position(|(c, _, _)| c == channel) {
+            Some(i) => &mut self.channels[i],
+            None => {
+                self.channels
+                    .push((channel.to_string(), 0, Duration::zero()));
+                self.channels.last_mut().unwrap()
+            }
+        }
+    }
 
-        let printer = Box::new(TodayBsTv { epg_doc: html });
+    fn add(&mut self, channel: &str, duration: Option<Duration>) {
+        let entry = self.entry(channel);
+        entry.1 += 1;
+        entry.2 = entry.2 + duration.unwrap_or_else(Duration::zero);
+    }
 
-        Ok(printer)
+    fn write<W: Write>(&self, w: &mut W, lang: Lang) -> io::Result<()> {
+        writeln!(w)?;
+        for (channel, count, duration) in &self.channels {
+            let summary = lang.summary(*count, duration.num_hours(), duration.num_minutes() % 60);
+            writeln!(w, "{} {}", channel, summary)?;
+        }
+        Ok(())
     }
 }
 
-impl<T: Write> Printer<T> for TodayBsTv {
-    fn print(&self, w: T) {
-        let ch_selector = Selector::parse("div#ch_area ul li.topmost p").unwrap();
-        let channels = self
-            .epg_doc
-            .select(&ch_selector)
-            .map(|e| e.inner_html())
-            .collect::<Vec<_>>();
-        let channels = channels.iter().map(|s| s.trim()).collect::<Vec<_>>();
+/// 番組
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    /// チャンネルの表示名
+    pub channel: String,
+    /// リモコン番号
+    pub number: Option<String>,
+    /// リモコン番号を除いたチャンネル名
+    pub name: String,
+    pub start: Option<NaiveDateTime>,
+    pub end: Option<NaiveDateTime>,
+    pub title: String,
+    pub detail: Option<String>,
+}
 
-        let program_selector = Selector::parse("div#program_area ul").unwrap();
-        let future_selector = Selector::parse("li.sc-future").unwrap();
-        let title_selector = Selector::parse("p.program_title").unwrap();
+impl Program {
+    /// 放送時間
+    /// 終了時刻が開始時刻より前の番組表は誤りなので`None`
+    fn duration(&self) -> Option<Duration> {
+        let (start, end) = (self.start?, self.end?);
+        (start <= end).then(|| end - start)
+    }
 
-        let program_area = self.epg_doc.select(&program_selector);
-        let mut buf = io::BufWriter::new(w);
-        for (i, ul) in program_area.enumerate() {
-            writeln!(buf, "{}", channels[i].color(BSCOLOR)).unwrap();
-            for li in ul.select(&future_selector) {
-                let start = li.value().attr("s").unwrap();
-                let start_hours = start.get(8..10).unwrap();
-                let start_minutes = start.get(10..12).unwrap();
-                let end = li.value().attr("e").unwrap();
-                let end_hours = end.get(8..10).unwrap();
-                let end_minutes = end.get(10..12).unwrap();
-                if let Some(title) = li.select(&title_selector).next() {
-                    writeln!(
-                        buf,
-                        "{}:{} ~ {}:{} {}",
-                        start_hours,
-                        start_minutes,
-                        end_hours,
-                        end_minutes,
-                        unescape(title.inner_html())
-                    )
-                    .unwrap();
-                }
-            }
+    /// `t`に放送中か
+    /// 開始時刻は含み終了時刻は含まない。時刻が分からなければ`false`
+    pub fn is_airing_at(&self, t: NaiveDateTime) -> bool {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) => start <= t && t < end,
+            _ => false,
+        }
+    }
+
+    /// `from`から`to`までの間に少しでも放送するか
+    /// 終了時刻ちょうどに始まる区間とは重ならない。時刻が分からなければ`false`
+    pub fn overlaps(&self, from: NaiveDateTime, to: NaiveDateTime) -> bool {
+        match (self.start, self.end) {
+            (Some(start), Some(end)) => start < to && from < end,
+            _ => false,
         }
     }
 }
 
-pub struct WeekBsTv {
-    epg_docs: Vec<Html>,
+/// 1チャンネル分の番組列から放送予定の番組を取り出す
+/// `s`属性と`e`属性を解釈できない番組は時刻なしとして扱う
+fn parse_programs(column: &Column, options: &Options) -> Vec<Program> {
+    let future_selector = &selectors().future;
+
+    let now = options.clock.now().naive_local();
+    let mut programs = vec![];
+    for li in column.ul.select(future_selector) {
+        if options.since_now && has_started(li.value().attr("s").unwrap_or_default(), now) {
+            continue;
+        }
+        programs.extend(parse_program(li, column, options));
+    }
+    programs
 }
 
-impl WeekBsTv {
-    pub fn init<T: Write>() -> Result<Box<dyn Printer<T>>> {
-        let mut datetime = Local::now();
-        if datetime.hour() < TV_GUIDE_START_TIME {
-            datetime = Local::now() + Duration::days(-1);
+/// 番組の`li`要素を解釈する
+/// タイトルがないか検索条件に一致しなければ`None`
+fn parse_program(li: ElementRef, column: &Column, options: &Options) -> Option<Program> {
+    let channel = &column.label;
+    let title_selector = &selectors().title;
+    let time = |name| {
+        let time = li
+            .value()
+            .attr(name)
+            .and_then(|t| NaiveDateTime::parse_from_str(t, "%Y%m%d%H%M").ok());
+        if time.is_none() {
+            log::trace!("no valid `{}` time for a program of {}", name, channel);
         }
-        const WEEK_COUNT: usize = 8;
-        let mut urls: [String; WEEK_COUNT] = Default::default();
-        for index in urls.iter_mut().take(WEEK_COUNT) {
-            let today = datetime.format("%Y%m%d");
-            let url = format!("https://bangumi.org/epg/bs?broad_cast_date={}", today);
-            *index = url;
-            datetime += Duration::days(1);
+        time
+    };
+    let title = match program_title(li, title_selector) {
+        Some(title) => title,
+        None => {
+            log::trace!("skipped a program of {} without title", channel);
+            return None;
         }
-        let htmls = async_get_htmls(urls.to_vec())?;
-        let printer = Box::new(WeekBsTv { epg_docs: htmls });
+    };
+    if !options.is_match(&title) {
+        return None;
+    }
+    Some(Program {
+        channel: channel.to_string(),
+        number: column.channel.number.clone(),
+        name: column.channel.name.clone(),
+        start: time("s"),
+        end: time("e"),
+        title,
+        detail: program_detail(li, options),
+    })
+}
 
-        Ok(printer)
+/// 時間帯ごとの表を組み立てる
+/// 行は1時間ごとの時間帯、列はチャンネルで、その時間帯に始まる番組を並べる
+fn time_grid(
+    channels: &[String],
+    programs: &[Program],
+    width: usize,
+    ellipsis: &str,
+) -> Vec<String> {
+    const TIME_COLUMN: usize = 6;
+    const MIN_COLUMN: usize = 8;
+    let column = (width.saturating_sub(TIME_COLUMN) / channels.len().max(1)).max(MIN_COLUMN);
+    let cell = |text: &str| {
+        let text = truncate_text(text, 0, column - 1, ellipsis);
+        let padding = column - display_width(&text);
+        format!("{}{}", text, " ".repeat(padding))
+    };
+    let row = |time: &str, cells: Vec<String>| {
+        let line = format!("{:<6}{}", time, cells.concat());
+        line.trim_end().to_string()
+    };
+
+    let mut slots: Vec<NaiveDateTime> = programs
+        .iter()
+        .filter_map(|p| p.start)
+        .map(|start| start.with_minute(0).unwrap())
+        .collect();
+    slots.sort();
+    slots.dedup();
+
+    let mut lines = vec![row("", channels.iter().map(|c| cell(c)).collect())];
+    for slot in slots {
+        let cells = channels
+            .iter()
+            .map(|channel| {
+                let titles = programs
+                    .iter()
+                    .filter(|p| &p.channel == channel)
+                    .filter(|p| p.start.map(|s| s.with_minute(0).unwrap()) == Some(slot))
+                    .map(|p| p.title.as_str())
+                    .collect::<Vec<_>>();
+                cell(&titles.join("/"))
+            })
+            .collect();
+        lines.push(row(&slot.format("%R").to_string(), cells));
     }
+    lines
 }
 
-impl<T: Write> Printer<T> for WeekBsTv {
-    fn print(&self, w: T) {
-        let mut buf = io::BufWriter::new(w);
-        for epg_doc in &self.epg_docs {
-            let ch_selector = Selector::parse("div#ch_area ul li.topmost p").unwrap();
-            let channels = epg_doc
-                .select(&ch_selector)
-                .map(|e| e.inner_html())
-                .collect::<Vec<_>>();
-            let channels = channels.iter().map(|s| s.trim()).collect::<Vec<_>>();
-
-            let program_selector = Selector::parse("div#program_area ul").unwrap();
-            let future_selector = Selector::parse("li.sc-future").unwrap();
-            let title_selector = Selector::parse("p.program_title").unwrap();
-
-            let program_area = epg_doc.select(&program_selector);
-            for (i, ul) in program_area.enumerate() {
-                for li in ul.select(&future_selector) {
-                    let start = li.value().attr("s").unwrap();
-                    let end = li.value().attr("e").unwrap();
-                    let start = NaiveDateTime::parse_from_str(start, "%Y%m%d%H%M").unwrap();
-                    let end = NaiveDateTime::parse_from_str(end, "%Y%m%d%H%M").unwrap();
-                    if let Some(title) = li.select(&title_selector).next() {
-                        writeln!(
-                            buf,
-                            "{} {} ~ {} {}",
-                            channels[i],
-                            start.format("%a %R"),
-                            end.format("%a %R"),
-                            unescape(title.inner_html())
-                        )
-                        .unwrap();
-                    }
+/// `--template`があればその書式で、なければ`--format`の形式で番組を書き出す
+fn write_programs_as<W: Write>(
+    w: &mut W,
+    programs: &[Program],
+    options: &Options,
+) -> io::Result<()> {
+    match &options.template {
+        Some(template) => {
+            for program in programs {
+                writeln!(w, "{}", template.render(program))?;
+            }
+            Ok(())
+        }
+        None => write_formatted(w, programs, options.format, options.utc),
+    }
+}
+
+/// テキスト以外の形式で番組を書き出す
+/// `utc`なら日本時間の番組表の時刻を協定世界時にして`Z`を付ける
+fn write_formatted<W: Write>(
+    w: &mut W,
+    programs: &[Program],
+    format: Format,
+    utc: bool,
+) -> io::Result<()> {
+    let (datetime, ical_datetime) = if utc {
+        ("%Y-%m-%dT%H:%M:%SZ", "%Y%m%dT%H%M%SZ")
+    } else {
+        ("%Y-%m-%dT%H:%M:%S", "%Y%m%dT%H%M%S")
+    };
+    let zone = |t: NaiveDateTime| if utc { to_utc(t) } else { t };
+    let time = |t: Option<NaiveDateTime>| t.map(|t| zone(t).format(datetime).to_string());
+    let json = |p: &Program| {
+        serde_json::json!({
+            "channel": p.channel,
+            "start": time(p.start),
+            "end": time(p.end),
+            "title": p.title,
+            "detail": p.detail,
+        })
+    };
+    match format {
+        Format::Text => unreachable!("text is written by each printer"),
+        Format::Json => {
+            let programs = programs.iter().map(json).collect::<Vec<_>>();
+            serde_json::to_writer_pretty(&mut *w, &programs)?;
+            writeln!(w)
+        }
+        Format::Ndjson => {
+            // 受け取る側が1行ずつ処理できるように書くたびに流す
+            for p in programs {
+                serde_json::to_writer(&mut *w, &json(p))?;
+                writeln!(w)?;
+                w.flush()?;
+            }
+            Ok(())
+        }
+        Format::Csv | Format::Tsv => {
+            let (separator, field): (&str, fn(&str) -> String) = match format {
+                Format::Csv => (",", csv_field),
+                _ => ("\t", tsv_field),
+            };
+            writeln!(
+                w,
+                "{}",
+                ["channel", "start", "end", "title"].join(separator)
+            )?;
+            for p in programs {
+                let row = [
+                    p.channel.clone(),
+                    time(p.start).unwrap_or_default(),
+                    time(p.end).unwrap_or_default(),
+                    p.title.clone(),
+                ];
+                let row = row.iter().map(|f| field(f)).collect::<Vec<_>>();
+                writeln!(w, "{}", row.join(separator))?;
+            }
+            Ok(())
+        }
+        Format::Markdown => {
+            writeln!(w, "| channel | start | end | title |")?;
+            writeln!(w, "| --- | --- | --- | --- |")?;
+            for p in programs {
+                writeln!(
+                    w,
+                    "| {} | {} | {} | {} |",
+                    markdown_cell(&p.channel),
+                    format_time(p.start, "%m/%d %R"),
+                    format_time(p.end, "%m/%d %R"),
+                    markdown_cell(&p.title)
+                )?;
+            }
+            Ok(())
+        }
+        Format::Ical => {
+            write!(
+                w,
+                "BEGIN:VCALENDAR\r\nVERSION:2.0\r\nPRODID:-//tvnow//EN\r\n"
+            )?;
+            for (i, p) in programs.iter().enumerate() {
+                // 時刻のない番組は予定にできない
+                let (start, end) = match (p.start, p.end) {
+                    (Some(start), Some(end)) => (zone(start), zone(end)),
+                    _ => continue,
+                };
+                write!(w, "BEGIN:VEVENT\r\n")?;
+                write!(w, "UID:{}-{}@tvnow\r\n", start.format(ical_datetime), i)?;
+                write!(w, "DTSTART:{}\r\n", start.format(ical_datetime))?;
+                write!(w, "DTEND:{}\r\n", end.format(ical_datetime))?;
+                write!(w, "SUMMARY:{}\r\n", ical_text(&p.title))?;
+                write!(w, "LOCATION:{}\r\n", ical_text(&p.channel))?;
+                if let Some(detail) = &p.detail {
+                    write!(w, "DESCRIPTION:{}\r\n", ical_text(detail))?;
                 }
+                write!(w, "END:VEVENT\r\n")?;
             }
+            write!(w, "END:VCALENDAR\r\n")
         }
     }
 }
 
-fn get_html(url: &str) -> Result<Html> {
-    let s = task::block_on(get_response_body_string(url))?;
-    let html = Html::parse_document(&s);
-    Ok(html)
+/// 番組表の日本時間(UTC+9、夏時間なし)を協定世界時にする
+fn to_utc(time: NaiveDateTime) -> NaiveDateTime {
+    time - Duration::hours(JST_OFFSET_HOURS)
 }
 
-async fn get_response_body_string(url: &str) -> Result<String> {
-    let rbs = surf::get(url)
-        .recv_string()
-        .await
-        .map_err(|err| anyhow!(err))
-        .context("Failed to fetch from bangumi.org")?;
+/// 区切り文字や引用符を含むCSVのフィールドを引用符で囲む
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
 
-    Ok(rbs)
+/// TSVのフィールドのタブと改行を`\t`、`\n`のようにエスケープする
+fn tsv_field(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
 }
 
-async fn multiple_requests(urls: Vec<String>) -> Vec<Result<String>> {
-    let mut handles = vec![];
-    for url in urls {
-        handles.push(
-            task::spawn_local(async move { get_response_body_string(&url).await })
-        );
+/// Markdownの表のセルに書けるようにする
+fn markdown_cell(s: &str) -> String {
+    s.replace('|', "\\|").replace(['\n', '\r'], " ")
+}
+
+/// iCalendarのTEXT値をエスケープする
+fn ical_text(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// `page`ページ目の番組と全体のページ数
+fn paginate(programs: Vec<Program>, page: Page) -> (Vec<Program>, usize) {
+    let pages = ((programs.len() + page.size - 1) / page.size).max(1);
+    let programs = programs
+        .into_iter()
+        .skip((page.number - 1) * page.size)
+        .take(page.size)
+        .collect();
+    (programs, pages)
+}
+
+/// 開始時刻順に並べ替える
+/// 同じ時刻ならチャンネル順のまま、時刻のない番組は最後
+fn sort_programs(programs: &mut [Program]) {
+    programs.sort_by_key(|p| (p.start.is_none(), p.start));
+}
+
+/// 時刻を表示する
+/// 時刻がなければ`--:--`
+fn format_time(time: Option<NaiveDateTime>, fmt: &str) -> String {
+    match time {
+        Some(time) => time.format(fmt).to_string(),
+        None => "--:--".to_string(),
     }
+}
 
-    let mut body_strings = vec![];
-    for handle in handles {
-        body_strings.push(handle.await);
+/// 放送日`date`の番組表での時刻
+/// 翌日以降の時刻は番組表の慣習に合わせて`25:30`のように24時以降で表す
+fn guide_time(time: Option<NaiveDateTime>, date: NaiveDate) -> String {
+    match time {
+        Some(time) => {
+            let days = (time.date() - date).num_days().max(0);
+            let hour = time.hour() as i64 + days * 24;
+            format!("{:02}:{:02}", hour, time.minute())
+        }
+        None => format_time(None, "%R"),
     }
+}
 
-    body_strings
+/// `--flat`の`2024-05-03(金) 20:00-20:45`形式の放送日時
+fn flat_time(program: &Program, lang: Lang) -> String {
+    match program.start {
+        Some(start) => format!(
+            "{}({}) {}-{}",
+            start.format("%Y-%m-%d"),
+            lang.weekday(start.weekday()),
+            start.format("%R"),
+            format_time(program.end, "%R")
+        ),
+        None => format!("--:---{}", format_time(program.end, "%R")),
+    }
 }
 
-fn async_get_htmls(urls: Vec<String>) -> Result<Vec<Html>> {
-    let results = task::block_on(multiple_requests(urls));
-    let res_bodies = results.into_iter().collect::<Result<Vec<String>>>()?;
-    let htmls = res_bodies
+/// `--compact`の項目を` | `でつなぎ、`width`を超える手前で折り返す
+/// 項目は表示幅と表示する文字列の組
+fn wrap_compact(items: &[(usize, String)], width: Option<usize>) -> Vec<String> {
+    const SEPARATOR: &str = " | ";
+    let mut lines: Vec<String> = vec![];
+    let mut line_width = 0;
+    for (item_width, item) in items {
+        let next_width = line_width + SEPARATOR.len() + item_width;
+        let fits = match width {
+            Some(width) => next_width <= width,
+            None => true,
+        };
+        match lines.last_mut() {
+            Some(line) if fits => {
+                line.push_str(SEPARATOR);
+                line.push_str(item);
+                line_width = next_width;
+            }
+            _ => {
+                lines.push(item.clone());
+                line_width = *item_width;
+            }
+        }
+    }
+    lines
+}
+
+/// `indent`桁目から始まる文字列を`width`桁で折り返す
+/// 折り返した行は`indent`桁字下げする。ANSIエスケープシーケンスは幅に数えない
+fn wrap_text(text: &str, indent: usize, width: usize) -> String {
+    if width <= indent {
+        return text.to_string();
+    }
+    let mut wrapped = String::new();
+    let mut column = indent;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            wrapped.push(c);
+            for c in chars.by_ref() {
+                wrapped.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        let char_width = c.width().unwrap_or(0);
+        if column + char_width > width {
+            wrapped.push('\n');
+            wrapped.push_str(&" ".repeat(indent));
+            column = indent;
+        }
+        wrapped.push(c);
+        column += char_width;
+    }
+    wrapped
+}
+
+/// ANSIエスケープシーケンスを除いた表示幅
+fn display_width(text: &str) -> usize {
+    let mut width = 0;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            chars.by_ref().find(|c| c.is_ascii_alphabetic());
+            continue;
+        }
+        width += c.width().unwrap_or(0);
+    }
+    width
+}
+
+/// `indent`桁目から始まる文字列を`width`桁に収まるように切り詰めて`ellipsis`を付ける
+/// ANSIエスケープシーケンスは幅に数えず、切り捨てた後のものも残す
+fn truncate_text(text: &str, indent: usize, width: usize, ellipsis: &str) -> String {
+    if display_width(text) + indent <= width {
+        return text.to_string();
+    }
+    let limit = width.saturating_sub(indent + ellipsis.width());
+    let mut truncated = String::new();
+    let mut column = 0;
+    let mut cut = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == '\x1b' {
+            truncated.push(c);
+            for c in chars.by_ref() {
+                truncated.push(c);
+                if c.is_ascii_alphabetic() {
+                    break;
+                }
+            }
+            continue;
+        }
+        if cut {
+            continue;
+        }
+        let char_width = c.width().unwrap_or(0);
+        if column + char_width > limit {
+            truncated.push_str(ellipsis);
+            cut = true;
+            continue;
+        }
+        truncated.push(c);
+        column += char_width;
+    }
+    truncated
+}
+
+/// これから始まる番組
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Upcoming {
+    pub channel: String,
+    pub start: NaiveDateTime,
+    pub title: String,
+}
+
+/// 現在放送中の番組表を取得し放送中の番組を返す
+pub fn fetch_current(source: ChannelSource, options: &Options) -> Result<Vec<Program>> {
+    let html = get_html(&source.url(&options.base_url, None))?;
+    check_strict(&html, options)?;
+    check_channel_names(&html, options)?;
+    prefetch_details(&html, &selectors().current, options);
+    Ok(current_programs(&channel_columns(&html, options), options))
+}
+
+/// 放送中の番組が`minutes`分以内に終わるチャンネルを終わるのが早い順に並べる
+fn ending_columns<'a>(
+    columns: Vec<Column<'a>>,
+    now: NaiveDateTime,
+    minutes: i64,
+) -> Vec<Column<'a>> {
+    let current_selector = &selectors().current;
+    let mut ending = columns
+        .into_iter()
+        .filter_map(|column| {
+            let current = column.ul.select(current_selector).next()?;
+            let remaining = remaining_minutes(current.value().attr("e")?, now)?;
+            (remaining <= minutes).then_some((remaining, column))
+        })
+        .collect::<Vec<_>>();
+    ending.sort_by_key(|(remaining, _)| *remaining);
+    ending.into_iter().map(|(_, column)| column).collect()
+}
+
+/// 各チャンネルの放送中の番組
+fn current_programs(columns: &[Column], options: &Options) -> Vec<Program> {
+    let current_selector = &selectors().current;
+    columns
+        .iter()
+        .filter_map(|column| {
+            let current = column.ul.select(current_selector).next()?;
+            parse_program(current, column, options)
+        })
+        .collect()
+}
+
+/// 今日の番組表を取得し`lead`以内に始まる番組を返す
+/// 検索条件があれば一致する番組に絞り込む
+pub fn fetch_upcoming(
+    source: ChannelSource,
+    options: &Options,
+    lead: Duration,
+) -> Result<Vec<Upcoming>> {
+    let html = get_html(&source.url(&options.base_url, None))?;
+    check_strict(&html, options)?;
+    check_channel_names(&html, options)?;
+    Ok(upcoming_programs(
+        &html,
+        options,
+        options.clock.now().naive_local(),
+        lead,
+    ))
+}
+
+fn upcoming_programs(
+    doc: &Html,
+    options: &Options,
+    now: NaiveDateTime,
+    lead: Duration,
+) -> Vec<Upcoming> {
+    let future_selector = &selectors().future;
+    let title_selector = &selectors().title;
+
+    let mut upcoming = vec![];
+    for column in channel_columns(doc, options) {
+        for li in column.ul.select(future_selector) {
+            let start = li
+                .value()
+                .attr("s")
+                .and_then(|s| NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M").ok());
+            let start = match start {
+                Some(start) if now <= start && start - now <= lead => start,
+                _ => continue,
+            };
+            if let Some(title) = program_title(li, title_selector).filter(|t| options.is_match(t)) {
+                upcoming.push(Upcoming {
+                    channel: column.channel.name.clone(),
+                    start,
+                    title,
+                });
+            }
+        }
+    }
+    upcoming
+}
+
+/// 同じタイトルで時間が連続する番組を1つにまとめる
+fn merge_programs(programs: Vec<Program>) -> Vec<Program> {
+    let mut merged: Vec<Program> = vec![];
+    for program in programs {
+        match merged.last_mut() {
+            Some(last)
+                if last.title == program.title
+                    && last.end.is_some()
+                    && last.end == program.start =>
+            {
+                last.end = program.end;
+            }
+            _ => merged.push(program),
+        }
+    }
+    merged
+}
+
+/// チャンネル
+struct Channel {
+    /// リモコン番号
+    number: Option<String>,
+    name: String,
+}
+
+impl Channel {
+    /// `1 NHK総合`のように先頭にリモコン番号が付いたチャンネル名を分解する
+    fn parse(s: &str) -> Self {
+        let s = s.trim();
+        let digits = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+        let number = match digits {
+            0 => None,
+            _ => Some(s[..digits].to_string()),
+        };
+        let name = s[digits..].trim().to_string();
+        Channel { number, name }
+    }
+
+    fn remote_number(&self) -> Option<u32> {
+        self.number.as_deref().and_then(|n| n.parse().ok())
+    }
+
+    /// `--sort-channels`の並び順
+    /// 番号の無いチャンネルは最後
+    fn sort_key(&self) -> u32 {
+        self.remote_number().unwrap_or(u32::MAX)
+    }
+}
+
+fn parse_channels(doc: &Html) -> Vec<Channel> {
+    let ch_selector = &selectors().channel;
+    doc.select(ch_selector)
+        .map(|e| Channel::parse(&unescape(e.inner_html())))
+        .collect()
+}
+
+/// チャンネルとその番組列
+struct Column<'a> {
+    channel: Channel,
+    /// `[1] NHK総合`形式の表示名
+    label: String,
+    ul: ElementRef<'a>,
+}
+
+/// 番組列をチャンネルに対応付ける
+/// `--channel-number`指定時は一致するチャンネルだけ
+/// `--sort-channels`指定時はリモコン番号順(同じ番号はサイトの順)
+fn channel_columns<'a>(doc: &'a Html, options: &Options) -> Vec<Column<'a>> {
+    let channels = parse_channels(doc);
+    let labels = channel_labels(&channels);
+    let mut columns: Vec<_> = channels
+        .into_iter()
+        .zip(labels)
+        .zip(doc.select(&selectors().program_area))
+        .map(|((channel, label), ul)| Column { channel, label, ul })
+        .filter(|column| options.shows_channel(&column.channel))
+        .collect();
+    log::debug!(
+        "{} columns matched `{}` and the channel filters",
+        columns.len(),
+        PROGRAM_AREA_SELECTOR
+    );
+    if options.sort_channels {
+        columns.sort_by_key(|column| column.channel.sort_key());
+    }
+    columns
+}
+
+/// リモコン番号の桁を揃えた`[1] NHK総合`形式の表示名
+fn channel_labels(channels: &[Channel]) -> Vec<String> {
+    let width = channels
+        .iter()
+        .filter_map(|c| c.number.as_ref())
+        .map(|n| n.len())
+        .max()
+        .unwrap_or(0);
+    channels
         .iter()
-        .map(|b| Html::parse_document(b))
-        .collect::<Vec<Html>>();
-    Ok(htmls)
+        .map(|c| match &c.number {
+            Some(number) => format!("[{:>2$}] {}", number, c.name, width),
+            None => c.name.clone(),
+        })
+        .collect()
+}
+
+/// 放送日を求める
+/// `start_hour`時より前は前日の放送日として扱う
+fn broadcast_date(now: DateTime<Local>, start_hour: u32) -> NaiveDate {
+    let date = now.date_naive();
+    if now.hour() < start_hour {
+        date - Duration::days(1)
+    } else {
+        date
+    }
+}
+
+/// `s`属性の開始時刻を現在時刻と比較する
+/// 解釈できない場合は開始前として扱う
+fn has_started(start: &str, now: NaiveDateTime) -> bool {
+    NaiveDateTime::parse_from_str(start, "%Y%m%d%H%M")
+        .map(|start| start < now)
+        .unwrap_or_else(|_| {
+            log::trace!("unparsable start time {:?}; treated as not started", start);
+            false
+        })
+}
+
+/// `e`属性の終了時刻までの残り時間(分)
+fn remaining_minutes(end: &str, now: NaiveDateTime) -> Option<i64> {
+    let end = NaiveDateTime::parse_from_str(end, "%Y%m%d%H%M").ok()?;
+    let minutes = (end - now).num_minutes();
+    if minutes <= 0 {
+        return None;
+    }
+    Some(minutes)
+}
+
+/// 強調表示する語を含むタイトルを色付けする
+/// 大文字小文字は区別しない
+/// 検索中は一致した箇所を色付けする
+fn highlight(title: String, options: &Options) -> String {
+    match (&options.highlight, &options.search) {
+        (Some(keyword), _) if contains_ignore_case(&title, keyword) => {
+            title.color(options.theme.highlight()).bold().to_string()
+        }
+        (_, Some(search)) => highlight_matches(&title, search, options.theme),
+        _ => title,
+    }
+}
+
+fn highlight_matches(title: &str, search: &Regex, theme: Theme) -> String {
+    let mut highlighted = String::new();
+    let mut last = 0;
+    for m in search.find_iter(title).filter(|m| !m.as_str().is_empty()) {
+        highlighted.push_str(&title[last..m.start()]);
+        highlighted.push_str(&m.as_str().color(theme.highlight()).bold().to_string());
+        last = m.end();
+    }
+    highlighted.push_str(&title[last..]);
+    highlighted
+}
+
+fn contains_ignore_case(title: &str, keyword: &str) -> bool {
+    title.to_lowercase().contains(&keyword.to_lowercase())
+}
+
+/// 番組タイトルを取得する
+/// タイトルが無い、または空白のみの番組は`None`
+fn program_title(li: ElementRef, title_selector: &Selector) -> Option<String> {
+    program_text(li, title_selector)
+}
+
+/// `--detail`指定時に番組の概要を取得する
+fn program_detail(li: ElementRef, options: &Options) -> Option<String> {
+    if !options.detail {
+        return None;
+    }
+    let detail_selector = &selectors().detail;
+    program_text(li, detail_selector).or_else(|| {
+        let url = detail_url(li, &options.base_url)?;
+        DETAILS.lock().ok()?.get(&url).cloned()
+    })
+}
+
+/// 番組の詳細ページのURL
+/// 相対パスは`base_url`からのパスとみなす
+fn detail_url(li: ElementRef, base_url: &str) -> Option<String> {
+    let href = li
+        .select(&selectors().detail_link)
+        .next()?
+        .value()
+        .attr("href")?;
+    if href.starts_with("http://") || href.starts_with("https://") {
+        Some(href.to_string())
+    } else {
+        Some(format!(
+            "{}/{}",
+            base_url.trim_end_matches('/'),
+            href.trim_start_matches('/')
+        ))
+    }
+}
+
+/// 詳細ページの概要
+/// 概要が無い、または空白のみのページは`None`
+fn parse_synopsis(html: &str) -> Option<String> {
+    let doc = Html::parse_document(html);
+    let text = doc
+        .select(&selectors().synopsis)
+        .filter_map(|meta| meta.value().attr("content"))
+        .map(str::trim)
+        .find(|text| !text.is_empty())?;
+    Some(text.to_string())
+}
+
+/// `--detail`指定時に、番組表に概要が載っていない番組の詳細ページを並行して取得しておく
+/// 取得できなかったページや概要の無いページの番組は概要なしで表示する
+fn prefetch_details(doc: &Html, selector: &Selector, options: &Options) {
+    if !options.detail {
+        return;
+    }
+    let urls = detail_urls(doc, selector, options);
+    if urls.is_empty() {
+        return;
+    }
+    log::info!("fetching {} detail pages", urls.len());
+    let bodies = task::block_on(multiple_requests(urls.clone(), options));
+    let synopses: Vec<_> = urls
+        .into_iter()
+        .zip(bodies)
+        .filter_map(|(url, body)| match body {
+            Ok(body) => match parse_synopsis(&body) {
+                Some(synopsis) => Some((url, synopsis)),
+                None => {
+                    log::debug!("no synopsis in {}", url);
+                    None
+                }
+            },
+            Err(err) => {
+                log::warn!("could not fetch {}: {}", url, err);
+                None
+            }
+        })
+        .collect();
+    if let Ok(mut details) = DETAILS.lock() {
+        details.extend(synopses);
+    }
+}
+
+/// 表示するチャンネルの`selector`に一致する番組のうち、概要を取得していない番組の詳細ページのURL
+/// 番組表に概要が載っている番組と取得済みのページは除く
+fn detail_urls(doc: &Html, selector: &Selector, options: &Options) -> Vec<String> {
+    let details = DETAILS
+        .lock()
+        .map(|details| details.clone())
+        .unwrap_or_default();
+    let mut seen = HashSet::new();
+    channel_columns(doc, options)
+        .into_iter()
+        .flat_map(|column| column.ul.select(selector))
+        .filter(|li| program_text(*li, &selectors().detail).is_none())
+        .filter_map(|li| detail_url(li, &options.base_url))
+        .filter(|url| !details.contains_key(url) && seen.insert(url.clone()))
+        .collect()
+}
+
+fn program_text(li: ElementRef, selector: &Selector) -> Option<String> {
+    let text = li.select(selector).next()?.inner_html();
+    let text = unescape(text);
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
+
+fn get_html(url: &str) -> Result<Html> {
+    let s = get_body(url)?;
+    let html = parse_document(&s)?;
+    Ok(html)
+}
+
+fn get_body(url: &str) -> Result<String> {
+    task::block_on(get_response_body_string(url))
+}
+
+/// 番組が一つでも載っているか
+fn has_programs(doc: &Html) -> bool {
+    doc.select(&selectors().program_area)
+        .any(|ul| ul.select(&selectors().program).next().is_some())
+}
+
+/// 番組表のHTMLを解析する
+/// チャンネルか番組列が一つも無い場合や、各チャンネルの番組列にチャンネル名が対応付けられない場合はエラー
+fn parse_document(s: &str) -> Result<Html> {
+    let started = Instant::now();
+    let html = Html::parse_document(s);
+    let ch_selector = &selectors().channel;
+    let program_selector = &selectors().program_area;
+    let channels = html.select(ch_selector).count();
+    let columns = html.select(program_selector).count();
+    log::debug!(
+        "parsed {} bytes in {:?}: {} channels matched `{}`, {} columns matched `{}`",
+        s.len(),
+        started.elapsed(),
+        channels,
+        CHANNEL_SELECTOR,
+        columns,
+        PROGRAM_AREA_SELECTOR
+    );
+    if channels != columns {
+        log::debug!("channels without a program column are dropped");
+    }
+    // 番組の無い放送日ではなく、セレクタが合わなくなったとみなす
+    if channels == 0 || columns == 0 {
+        return Err(TvnowError::Structure);
+    }
+    if columns > channels {
+        return Err(TvnowError::Parse(format!(
+            "{} program columns for {} channels",
+            columns, channels
+        )));
+    }
+    Ok(html)
+}
+
+/// `--channel`の名前がどのチャンネル名にも含まれなければエラー
+/// 大文字小文字は区別しない
+fn check_channel_names(doc: &Html, options: &Options) -> Result<()> {
+    if !options.check_channel_names {
+        return Ok(());
+    }
+    let channels = parse_channels(doc);
+    for name in &options.channel_names {
+        let lower = name.to_lowercase();
+        if !channels
+            .iter()
+            .any(|channel| channel.name.to_lowercase().contains(&lower))
+        {
+            return Err(TvnowError::UnknownChannel {
+                name: name.clone(),
+                message: options.lang.unknown_channel(name),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// `--strict`指定時に番組表を検証する
+/// チャンネルと番組列の数が一致し、全ての番組に時刻とタイトルがあること
+fn check_strict(doc: &Html, options: &Options) -> Result<()> {
+    if !options.strict {
+        return Ok(());
+    }
+    let ch_selector = &selectors().channel;
+    let program_selector = &selectors().program_area;
+    let li_selector = &selectors().program;
+    let title_selector = &selectors().title;
+    let channels = doc.select(ch_selector).count();
+    let columns = doc.select(program_selector).count();
+    if channels == 0 {
+        return Err(TvnowError::Strict("no channels".to_string()));
+    }
+    if columns != channels {
+        return Err(TvnowError::Strict(format!(
+            "{} program columns for {} channels",
+            columns, channels
+        )));
+    }
+    for (i, ul) in doc.select(program_selector).enumerate() {
+        for li in ul.select(li_selector) {
+            let attr = |name| li.value().attr(name).filter(|v: &&str| !v.is_empty());
+            if attr("s").is_none() || attr("e").is_none() {
+                return Err(TvnowError::Strict(format!(
+                    "program without start or end time in column {}",
+                    i + 1
+                )));
+            }
+            if program_title(li, title_selector).is_none() {
+                return Err(TvnowError::Strict(format!(
+                    "program without title in column {}",
+                    i + 1
+                )));
+            }
+        }
+    }
+    Ok(())
+}
+
+// 名前解決や接続ができないときのエラーメッセージ(小文字)
+const UNREACHABLE_MESSAGES: [&str; 8] = [
+    "failed to lookup address",
+    "name or service not known",
+    "temporary failure in name resolution",
+    "nodename nor servname provided",
+    "no such host",
+    "connection refused",
+    "network is unreachable",
+    "no route to host",
+];
+
+/// リクエストを送れなかったエラーを分類する
+/// 名前解決や接続ができなければネットワークに繋がっていないとみなす
+fn request_error(err: &surf::Error) -> TvnowError {
+    let refused = err.downcast_ref::<io::Error>().map_or(false, |err| {
+        matches!(
+            err.kind(),
+            io::ErrorKind::ConnectionRefused | io::ErrorKind::AddrNotAvailable
+        )
+    });
+    let message = err.to_string();
+    let lowercase = message.to_lowercase();
+    if refused || UNREACHABLE_MESSAGES.iter().any(|m| lowercase.contains(m)) {
+        // 元のエラーは--verboseで確認できるようにする
+        log::info!("unreachable: {}", message);
+        TvnowError::Offline(message)
+    } else {
+        TvnowError::Http(message)
+    }
+}
+
+async fn get_response_body_string(url: &str) -> Result<String> {
+    log::info!("GET {}", url);
+    let started = Instant::now();
+    let req = CLIENT.get(url).header("Accept-Encoding", "gzip, deflate");
+    let mut res = future::timeout(REQUEST_TIMEOUT, req)
+        .await
+        .map_err(|_| TvnowError::Timeout)?
+        .map_err(|err| request_error(&err))?;
+    log::info!("{} {} in {:?}", res.status(), url, started.elapsed());
+    if !res.status().is_success() {
+        return Err(TvnowError::BadStatus(res.status().into()));
+    }
+    let encoding = res
+        .header("Content-Encoding")
+        .map(|v| v.last().as_str().to_string());
+    let content_type = res
+        .header("Content-Type")
+        .map(|v| v.last().as_str().to_string());
+    let body = future::timeout(REQUEST_TIMEOUT, res.body_bytes())
+        .await
+        .map_err(|_| TvnowError::Timeout)?
+        .map_err(|err| TvnowError::Http(err.to_string()))?;
+    log::debug!(
+        "{} bytes ({}) from {} in {:?}",
+        body.len(),
+        encoding.as_deref().unwrap_or("identity"),
+        url,
+        started.elapsed()
+    );
+    let body = decompress_body(body, encoding.as_deref())?;
+    let rbs = decode_text(&body, content_type.as_deref())?;
+
+    Ok(rbs)
+}
+
+/// `Content-Encoding`に従ってレスポンスを展開する
+fn decompress_body(body: Vec<u8>, encoding: Option<&str>) -> Result<Vec<u8>> {
+    let mut decoded = vec![];
+    let body = match encoding {
+        Some("gzip") => {
+            GzDecoder::new(&body[..])
+                .read_to_end(&mut decoded)
+                .map_err(|err| TvnowError::Decode(err.to_string()))?;
+            decoded
+        }
+        Some("deflate") => {
+            ZlibDecoder::new(&body[..])
+                .read_to_end(&mut decoded)
+                .map_err(|err| TvnowError::Decode(err.to_string()))?;
+            decoded
+        }
+        _ => body,
+    };
+    Ok(body)
+}
+
+/// レスポンスを文字列にする
+/// 文字コードは`Content-Type`、HTMLの`meta`の順に探し、見つからなければUTF-8とする
+fn decode_text(body: &[u8], content_type: Option<&str>) -> Result<String> {
+    let label = content_type
+        .and_then(charset_param)
+        .or_else(|| meta_charset(body))
+        .unwrap_or_else(|| "utf-8".to_string());
+    let encoding = Encoding::for_label(label.as_bytes())
+        .ok_or_else(|| TvnowError::Decode(format!("unknown charset {}", label)))?;
+    encoding
+        .decode_without_bom_handling_and_without_replacement(body)
+        .map(|text| text.into_owned())
+        .ok_or_else(|| TvnowError::Decode(format!("invalid {} bytes", encoding.name())))
+}
+
+/// `text/html; charset=UTF-8`から文字コードを取り出す
+fn charset_param(content_type: &str) -> Option<String> {
+    content_type.split(';').skip(1).find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"').to_string())
+    })
+}
+
+/// HTMLの先頭の`<meta charset="...">`から文字コードを取り出す
+fn meta_charset(body: &[u8]) -> Option<String> {
+    let head = String::from_utf8_lossy(&body[..body.len().min(1024)]).to_ascii_lowercase();
+    let value = &head[head.find("charset=")? + "charset=".len()..];
+    let label = value
+        .trim_start_matches(['"', '\''])
+        .chars()
+        .take_while(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_'))
+        .collect::<String>();
+    (!label.is_empty()).then_some(label)
+}
+
+/// `options.concurrency`件ずつ並行して取得する
+/// 結果は`urls`の順
+async fn multiple_requests(urls: Vec<String>, options: &Options) -> Vec<Result<String>> {
+    let total = urls.len();
+    let queue = Rc::new(RefCell::new(
+        urls.into_iter().enumerate().collect::<VecDeque<_>>(),
+    ));
+    let results = Rc::new(RefCell::new((0..total).map(|_| None).collect::<Vec<_>>()));
+    let done = Rc::new(Cell::new(0));
+    let mut handles = vec![];
+    for _ in 0..options.concurrency.max(1).min(total) {
+        let queue = Rc::clone(&queue);
+        let results = Rc::clone(&results);
+        let done = Rc::clone(&done);
+        let progress = options.progress.then_some(options.lang);
+        handles.push(task::spawn_local(async move {
+            while !is_interrupted() {
+                // 取得中に他のタスクが借用できるよう、取り出したらすぐに借用を終える
+                let next = queue.borrow_mut().pop_front();
+                let (index, url) = match next {
+                    Some(next) => next,
+                    None => break,
+                };
+                // 中断されたら取得中のリクエストは破棄する
+                let body =
+                    retry_with_jitter(&url, RETRY_BASE_DELAY, || get_response_body_string(&url))
+                        .race(wait_for_interrupt())
+                        .await;
+                // 終わった順に数える
+                done.set(done.get() + 1);
+                if let Some(lang) = progress {
+                    show_progress(Some(lang.fetching(done.get(), total)));
+                }
+                results.borrow_mut()[index] = Some(body);
+            }
+        }));
+    }
+
+    for handle in handles {
+        handle.await;
+    }
+    if options.progress {
+        show_progress(None);
+    }
+    if is_interrupted() {
+        return vec![Err(TvnowError::Interrupted)];
+    }
+
+    results
+        .take()
+        .into_iter()
+        .map(|body| body.expect("every url is fetched"))
+        .collect()
+}
+
+/// 一時的なエラーで失敗したら`MAX_RETRIES`回まで取得し直す
+/// 同時に失敗したリクエストが同じ時刻に再送してぶつからないよう、待ち時間にばらつきを持たせる
+async fn retry_with_jitter<F, Fut>(
+    url: &str,
+    base_delay: std::time::Duration,
+    mut fetch: F,
+) -> Result<String>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = Result<String>>,
+{
+    let mut attempt = 0;
+    loop {
+        match fetch().await {
+            Err(err) if err.is_transient() && attempt < MAX_RETRIES => {
+                let delay = backoff_delay(base_delay, attempt, jitter());
+                log::info!("retrying {} in {:?}: {}", url, delay, err);
+                task::sleep(delay).await;
+                attempt += 1;
+            }
+            Err(err) => {
+                log::warn!("failed {} after {} attempts: {}", url, attempt + 1, err);
+                return Err(err);
+            }
+            Ok(body) => {
+                if attempt > 0 {
+                    log::info!("fetched {} after {} attempts", url, attempt + 1);
+                }
+                return Ok(body);
+            }
+        }
+    }
+}
+
+/// `attempt`回目のやり直しまでの待ち時間
+/// 基準を回数ごとに倍にし、後半分を`jitter`(0以上1未満)でばらつかせる
+fn backoff_delay(
+    base_delay: std::time::Duration,
+    attempt: u32,
+    jitter: f64,
+) -> std::time::Duration {
+    let delay = base_delay * 2u32.pow(attempt);
+    delay / 2 + (delay / 2).mul_f64(jitter)
+}
+
+/// 0以上1未満の待ち時間のばらつき
+/// 現在時刻の1秒未満のナノ秒を使う
+/// 再送の時刻がリクエストごとにずれればよいので、乱数の依存は増やさない
+fn jitter() -> f64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.subsec_nanos());
+    nanos as f64 / 1_000_000_000.0
+}
+
+/// 取得中の番組表を中断させる
+/// Ctrl-Cのハンドラから呼ぶ
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// 中断されるまで待つ
+async fn wait_for_interrupt() -> Result<String> {
+    while !is_interrupted() {
+        task::sleep(INTERRUPT_POLL_INTERVAL).await;
+    }
+    Err(TvnowError::Interrupted)
+}
+
+/// 標準エラー出力の現在行を`message`に書き換える
+/// `None`なら行を消す
+fn show_progress(message: Option<String>) {
+    let mut stderr = io::stderr();
+    // 進捗は表示できなくても取得は続ける
+    let _ = stderr
+        .queue(MoveToColumn(0))
+        .and_then(|stderr| stderr.queue(Clear(ClearType::CurrentLine)));
+    if let Some(message) = message {
+        let _ = write!(stderr, "{}", message);
+    }
+    let _ = stderr.flush();
+}
+
+/// 番組表のHTMLを解析せずに取得する
+pub fn fetch_bodies(urls: Vec<String>, options: &Options) -> Result<Vec<String>> {
+    match urls.len() {
+        1 => Ok(vec![get_body(&urls[0])?]),
+        _ => async_get_bodies(urls, options),
+    }
+}
+
+fn async_get_bodies(urls: Vec<String>, options: &Options) -> Result<Vec<String>> {
+    let results = task::block_on(multiple_requests(urls, options));
+    results.into_iter().collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::clock::FixedClock;
+    use colored::control::set_override;
+
+    const CURRENT: &str = include_str!("../tests/fixtures/current.html");
+    const EMPTY_TITLE: &str = include_str!("../tests/fixtures/empty_title.html");
+    const BS: &str = include_str!("../tests/fixtures/bs.html");
+    const UNORDERED: &str = include_str!("../tests/fixtures/unordered.html");
+    const BOUNDARY_DAY1: &str = include_str!("../tests/fixtures/boundary_day1.html");
+    const BOUNDARY_DAY2: &str = include_str!("../tests/fixtures/boundary_day2.html");
+    const DETAIL_LINKS: &str = include_str!("../tests/fixtures/detail_links.html");
+    const DETAIL: &str = include_str!("../tests/fixtures/detail.html");
+    const DETAIL_EMPTY: &str = include_str!("../tests/fixtures/detail_empty.html");
+
+    fn fixture(html: &str) -> Html {
+        Html::parse_document(html)
+    }
+
+    /// fixtureの放送日(2024-01-01)のうち、番組がすべて終わった後の23:30として扱う
+    fn fixed_options() -> Options {
+        let now = Local.with_ymd_and_hms(2024, 1, 1, 23, 30, 0).unwrap();
+        Options {
+            clock: Arc::new(FixedClock(now)),
+            ..Options::default()
+        }
+    }
+
+    /// 解析済みの番組表から直接作れる印字器
+    trait FromFixture {
+        fn from_parts(source: ChannelSource, epg_doc: Html, options: Options) -> Self;
+    }
+
+    impl FromFixture for Tv {
+        fn from_parts(source: ChannelSource, epg_doc: Html, options: Options) -> Self {
+            Tv {
+                source,
+                epg_doc,
+                options,
+            }
+        }
+    }
+
+    impl FromFixture for TodayTv {
+        fn from_parts(source: ChannelSource, epg_doc: Html, options: Options) -> Self {
+            TodayTv {
+                source,
+                epg_doc,
+                options,
+            }
+        }
+    }
+
+    impl FromFixture for ChannelList {
+        fn from_parts(source: ChannelSource, epg_doc: Html, options: Options) -> Self {
+            ChannelList {
+                source,
+                epg_doc,
+                options,
+            }
+        }
+    }
+
+    /// fixtureのHTMLから印字器を作る
+    /// 時刻に左右されないよう`options`には`fixed_options`を使う
+    fn from_fixture<P: FromFixture>(source: ChannelSource, html: &str, options: Options) -> P {
+        P::from_parts(source, fixture(html), options)
+    }
+
+    fn render<P>(printer: P) -> String
+    where
+        P: for<'a> Printer<&'a mut Vec<u8>>,
+    {
+        // カラー化無効
+        set_override(false);
+        let mut out: Vec<u8> = vec![];
+        printer.print(&mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn count<P: Printer<io::Sink>>(printer: P) -> usize {
+        printer.print(io::sink()).unwrap()
+    }
+
+    /// 閉じたパイプのように書き込めない出力先
+    struct ClosedPipe;
+
+    impl Write for ClosedPipe {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_print_returns_write_error() {
+        let printer =
+            from_fixture::<ChannelList>(ChannelSource::Area(42), CURRENT, fixed_options());
+        let err = printer.print(ClosedPipe).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::BrokenPipe);
+        let printer = from_fixture::<Tv>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                format: Format::Csv,
+                ..fixed_options()
+            },
+        );
+        assert!(printer.print(ClosedPipe).is_err());
+    }
+
+    #[test]
+    fn test_channel_parse_works() {
+        let ch = Channel::parse(" 9 TOKYO MX1 ");
+        assert_eq!(ch.number.as_deref(), Some("9"));
+        assert_eq!(ch.name, "TOKYO MX1");
+        let ch = Channel::parse("ＢＳ日テレ");
+        assert_eq!(ch.number, None);
+        assert_eq!(ch.name, "ＢＳ日テレ");
+
+        let channels = [Channel::parse("3 放送大学"), Channel::parse("141 BS日テレ")];
+        assert_eq!(
+            channel_labels(&channels),
+            ["[  3] 放送大学", "[141] BS日テレ"]
+        );
+    }
+
+    #[test]
+    fn test_channel_name_is_unescaped() {
+        let doc = fixture(
+            r#"<div id="ch_area"><ul>
+            <li class="topmost"><p>9 TOKYO&nbsp;MX1</p></li>
+            <li class="topmost"><p>7 テレ東&amp;BS</p></li>
+            </ul></div>"#,
+        );
+        let channels = parse_channels(&doc);
+        assert_eq!(channels[0].name, "TOKYO\u{a0}MX1");
+        assert_eq!(channels[1].name, "テレ東&BS");
+    }
+
+    #[test]
+    fn test_channel_source_url_works() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1);
+        assert_eq!(
+            ChannelSource::Area(42).url(BASE_URL, None),
+            "https://bangumi.org/epg/td?ggm_group_id=42"
+        );
+        assert_eq!(
+            ChannelSource::Area(42).url(BASE_URL, date),
+            "https://bangumi.org/epg/td?broad_cast_date=20240101&ggm_group_id=42"
+        );
+        assert_eq!(
+            ChannelSource::Bs.url(BASE_URL, None),
+            "https://bangumi.org/epg/bs"
+        );
+        assert_eq!(
+            ChannelSource::Bs.url(BASE_URL, date),
+            "https://bangumi.org/epg/bs?broad_cast_date=20240101"
+        );
+        assert_eq!(
+            ChannelSource::Cs.url(BASE_URL, None),
+            "https://bangumi.org/epg/cs"
+        );
+        assert_eq!(
+            ChannelSource::Cs.url(BASE_URL, date),
+            "https://bangumi.org/epg/cs?broad_cast_date=20240101"
+        );
+        assert_eq!(
+            ChannelSource::Bs.url("http://127.0.0.1:8080/", None),
+            "http://127.0.0.1:8080/epg/bs"
+        );
+    }
+
+    #[test]
+    fn test_urls_works() {
+        let options = Options::default();
+        assert_eq!(
+            Tv::urls(ChannelSource::Bs, &options),
+            vec!["https://bangumi.org/epg/bs"]
+        );
+        assert_eq!(
+            TodayTv::urls(ChannelSource::Area(23), &options),
+            vec!["https://bangumi.org/epg/td?ggm_group_id=23"]
+        );
+        let urls = WeekTv::urls(ChannelSource::Area(23), &options);
+        assert_eq!(urls.len(), 8);
+        assert!(urls
+            .iter()
+            .all(|url| url.contains("broad_cast_date=") && url.ends_with("&ggm_group_id=23")));
+
+        let options = Options {
+            day_offset: 1,
+            ..Options::default()
+        };
+        let url = &TodayTv::urls(ChannelSource::Bs, &options)[0];
+        assert!(url.starts_with("https://bangumi.org/epg/bs?broad_cast_date="));
+    }
+    #[test]
+    fn test_tv_print_works() {
+        let printer = from_fixture::<Tv>(ChannelSource::Area(42), CURRENT, fixed_options());
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合 クローズアップ現代\n[2] Eテレ 現在放送していません\n[4] 日テレ 映画&トーク\n"
+        );
+    }
+
+    #[test]
+    fn test_print_count_works() {
+        let tv = |options| from_fixture::<Tv>(ChannelSource::Area(42), CURRENT, options);
+        assert_eq!(count(tv(fixed_options())), 2);
+        assert_eq!(
+            count(tv(Options {
+                search: Some(Regex::new("大河").unwrap()),
+                ..fixed_options()
+            })),
+            0
+        );
+        let printer = from_fixture::<TodayTv>(ChannelSource::Area(42), CURRENT, fixed_options());
+        assert_eq!(count(printer), 2);
+        let printer =
+            from_fixture::<ChannelList>(ChannelSource::Area(42), CURRENT, fixed_options());
+        assert_eq!(count(printer), 3);
+        let printer = WeekTv::new(&[CURRENT, BS], &fixed_options()).unwrap();
+        assert_eq!(count(printer), 4);
+    }
+
+    #[test]
+    fn test_today_tv_day_end_works() {
+        let at = |day, hour| Options {
+            clock: Arc::new(FixedClock(
+                Local.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap(),
+            )),
+            ..Options::default()
+        };
+        let day_end = |s| NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M").unwrap();
+        assert_eq!(TodayTv::day_end(&at(1, 20)), day_end("202401020500"));
+        assert_eq!(TodayTv::day_end(&at(2, 4)), day_end("202401020500"));
+        assert_eq!(TodayTv::day_end(&at(2, 5)), day_end("202401030500"));
+
+        let printer = from_fixture::<TodayTv>(ChannelSource::Area(42), BOUNDARY_DAY2, at(1, 20));
+        assert_eq!(render(printer), "[1] NHK総合\n28:00 ~ 29:30 映画\n");
+        let printer = from_fixture::<TodayTv>(
+            ChannelSource::Area(42),
+            BOUNDARY_DAY2,
+            Options {
+                guide_start_hour: 6,
+                ..at(1, 20)
+            },
+        );
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合\n28:00 ~ 29:30 映画\n29:30 ~ 30:00 おはよう日本\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_days_works() {
+        let htmls = [CURRENT, EMPTY_TITLE, BS, BOUNDARY_DAY1, BOUNDARY_DAY2];
+        let options = Options {
+            detail: true,
+            ..fixed_options()
+        };
+        let sequential = htmls
+            .iter()
+            .map(|html| Day::parse(html, &options).unwrap())
+            .collect::<Vec<_>>();
+        let parallel = parse_days(&htmls, &options)
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(parallel, sequential);
+        assert!(matches!(
+            parse_days(&[CURRENT, "<html></html>"], &options)[1],
+            Err(TvnowError::Structure)
+        ));
+    }
+
+    #[test]
+    fn test_week_dedup_works() {
+        let printer = WeekTv::new(&[BOUNDARY_DAY1, BOUNDARY_DAY2], &fixed_options()).unwrap();
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合 Mon 23:00 ~ Tue 04:00 ニュース\n\
+             [1] NHK総合 Tue 04:00 ~ Tue 05:30 映画\n\
+             [1] NHK総合 Tue 05:30 ~ Tue 06:00 おはよう日本\n"
+        );
+        let printer = WeekTv::new(
+            &[BOUNDARY_DAY1, BOUNDARY_DAY2],
+            &Options {
+                sort: SortOrder::Time,
+                ..fixed_options()
+            },
+        )
+        .unwrap();
+        assert_eq!(count(printer), 3);
+    }
+
+    #[test]
+    fn test_since_until_works() {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M").ok();
+        let week = |since, until| {
+            WeekTv::new(
+                &[CURRENT, BS],
+                &Options {
+                    since,
+                    until,
+                    ..fixed_options()
+                },
+            )
+            .unwrap()
+        };
+        assert_eq!(count(week(None, None)), 4);
+        assert_eq!(count(week(at("202401012030"), None)), 2);
+        assert_eq!(count(week(at("202401012000"), None)), 4);
+        assert_eq!(count(week(None, at("202401012100"))), 2);
+        assert_eq!(count(week(at("202401012000"), at("202401012001"))), 2);
+        assert_eq!(
+            render(week(at("202401012030"), None)),
+            "[141] BS日テレ Mon 21:00 ~ Mon 22:00 ドラマ\n\
+             [151] BS朝日 Mon 21:00 ~ Mon 21:54 世界の車窓から\n"
+        );
+    }
+
+    #[test]
+    fn test_channel_list_print_works() {
+        let printer =
+            from_fixture::<ChannelList>(ChannelSource::Area(42), CURRENT, fixed_options());
+        assert_eq!(render(printer), "[1] NHK総合\n[2] Eテレ\n[4] 日テレ\n");
+    }
+
+    #[test]
+    fn test_channel_number_works() {
+        let options = Options {
+            channel_number: Some(4),
+            ..fixed_options()
+        };
+        let printer = from_fixture::<Tv>(ChannelSource::Area(42), CURRENT, options.clone());
+        assert_eq!(render(printer), "[4] 日テレ 映画&トーク\n");
+        let printer = from_fixture::<ChannelList>(ChannelSource::Area(42), CURRENT, options);
+        assert_eq!(render(printer), "[4] 日テレ\n");
+        let printer = from_fixture::<TodayTv>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                channel_number: Some(2),
+                ..fixed_options()
+            },
+        );
+        assert_eq!(render(printer), "[2] Eテレ\n20:00 ~ 20:30 0655\n");
+    }
+
+    #[test]
+    fn test_channel_names_works() {
+        let printer = from_fixture::<ChannelList>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                channel_names: vec!["nhk".to_string(), "日テレ".to_string()],
+                ..fixed_options()
+            },
+        );
+        assert_eq!(render(printer), "[1] NHK総合\n[4] 日テレ\n");
+        let printer = from_fixture::<Tv>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                channel_names: vec!["テレ".to_string()],
+                channel_number: Some(2),
+                ..fixed_options()
+            },
+        );
+        assert_eq!(render(printer), "[2] Eテレ 0655\n");
+    }
+
+    #[test]
+    fn test_sort_channels_works() {
+        let printer =
+            from_fixture::<ChannelList>(ChannelSource::Area(42), UNORDERED, fixed_options());
+        assert_eq!(
+            render(printer),
+            "[8] フジテレビ\nローカル局\n[1] NHK総合\n[8] フジテレビ2\n"
+        );
+
+        let options = Options {
+            sort_channels: true,
+            ..fixed_options()
+        };
+        let printer =
+            from_fixture::<ChannelList>(ChannelSource::Area(42), UNORDERED, options.clone());
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合\n[8] フジテレビ\n[8] フジテレビ2\nローカル局\n"
+        );
+        let printer = from_fixture::<Tv>(ChannelSource::Area(42), UNORDERED, options);
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合 大河ドラマ\n[8] フジテレビ 月9\n[8] フジテレビ2 再放送\nローカル局 地域情報\n"
+        );
+    }
+
+    #[test]
+    fn test_today_tv_print_works() {
+        let printer = from_fixture::<TodayTv>(ChannelSource::Area(42), CURRENT, fixed_options());
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合\n20:00 ~ 21:00 大河ドラマ\n[2] Eテレ\n20:00 ~ 20:30 0655\n[4] 日テレ\n"
+        );
+    }
+
+    #[test]
+    fn test_program_is_airing_at_works() {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M").unwrap();
+        let program = Program {
+            channel: "[1] NHK総合".to_string(),
+            number: Some("1".to_string()),
+            name: "NHK総合".to_string(),
+            start: Some(at("202401012000")),
+            end: Some(at("202401012100")),
+            title: "大河ドラマ".to_string(),
+            detail: None,
+        };
+        assert!(!program.is_airing_at(at("202401011959")));
+        assert!(program.is_airing_at(at("202401012000")));
+        assert!(program.is_airing_at(at("202401012059")));
+        assert!(!program.is_airing_at(at("202401012100")));
+
+        let unknown = Program {
+            end: None,
+            ..program
+        };
+        assert!(!unknown.is_airing_at(at("202401012030")));
+    }
+
+    #[test]
+    fn test_program_overlaps_works() {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M").unwrap();
+        let program = Program {
+            channel: "[1] NHK総合".to_string(),
+            number: Some("1".to_string()),
+            name: "NHK総合".to_string(),
+            start: Some(at("202401012000")),
+            end: Some(at("202401012100")),
+            title: "大河ドラマ".to_string(),
+            detail: None,
+        };
+        assert!(program.overlaps(at("202401011900"), at("202401012001")));
+        assert!(program.overlaps(at("202401012030"), at("202401012040")));
+        assert!(program.overlaps(at("202401011900"), at("202401012200")));
+        assert!(program.overlaps(at("202401012059"), at("202401012200")));
+        // 終了時刻ちょうどから、開始時刻ちょうどまでの区間とは重ならない
+        assert!(!program.overlaps(at("202401012100"), at("202401012200")));
+        assert!(!program.overlaps(at("202401011900"), at("202401012000")));
+
+        let unknown = Program {
+            start: None,
+            ..program
+        };
+        assert!(!unknown.overlaps(at("202401011900"), at("202401012200")));
+    }
+
+    #[test]
+    fn test_template_parse_works() {
+        let template = "{channel}\t{start}-{end} {{{title}}}"
+            .parse::<Template>()
+            .unwrap();
+        assert_eq!(
+            template,
+            Template(vec![
+                Segment::Field(Field::Channel),
+                Segment::Text("\t".to_string()),
+                Segment::Field(Field::Start),
+                Segment::Text("-".to_string()),
+                Segment::Field(Field::End),
+                Segment::Text(" {".to_string()),
+                Segment::Field(Field::Title),
+                Segment::Text("}".to_string()),
+            ])
+        );
+        assert!(!template.needs_detail());
+        assert!("{title} {detail}"
+            .parse::<Template>()
+            .unwrap()
+            .needs_detail());
+        assert!("{name}".parse::<Template>().is_err());
+        assert!("{title".parse::<Template>().is_err());
+        assert!("title}".parse::<Template>().is_err());
+    }
+
+    #[test]
+    fn test_template_duration_works() {
+        let at = |s: &str| NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M").ok();
+        let program = |start, end| Program {
+            channel: "[1] NHK総合".to_string(),
+            number: Some("1".to_string()),
+            name: "NHK総合".to_string(),
+            start: at(start),
+            end: at(end),
+            title: "映画".to_string(),
+            detail: None,
+        };
+        let template = "{start}-{end} {duration}".parse::<Template>().unwrap();
+        // 24時をまたいで翌日の1時に終わる
+        assert_eq!(
+            template.render(&program("202401012330", "202401020100")),
+            "23:30-01:00 1:30"
+        );
+        // 終了が開始より前の誤った時刻は長さを出さない
+        assert_eq!(
+            template.render(&program("202401012100", "202401012000")),
+            "21:00-20:00 "
+        );
+        assert_eq!(
+            template.render(&program("202401012330", "")),
+            "23:30---:-- "
+        );
+    }
+
+    #[test]
+    fn test_template_print_works() {
+        let options = Options {
+            template: Some("{date} {start}-{end} {channel}: {title}".parse().unwrap()),
+            ..fixed_options()
+        };
+        let printer = from_fixture::<TodayTv>(ChannelSource::Area(42), CURRENT, options.clone());
+        assert_eq!(
+            render(printer),
+            "2024-01-01 20:00-21:00 NHK総合: 大河ドラマ\n\
+             2024-01-01 20:00-20:30 Eテレ: 0655\n"
+        );
+        let printer = from_fixture::<Tv>(ChannelSource::Area(42), CURRENT, options);
+        assert_eq!(
+            render(printer),
+            "2024-01-01 19:00-20:00 NHK総合: クローズアップ現代\n\
+             2024-01-01 18:56-20:54 日テレ: 映画&トーク\n"
+        );
+
+        let printer = from_fixture::<Tv>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                template: Some("{number} {title} ({duration})".parse().unwrap()),
+                ..fixed_options()
+            },
+        );
+        assert_eq!(
+            render(printer),
+            "1 クローズアップ現代 (1:00)\n4 映画&トーク (1:58)\n"
+        );
+        let printer = from_fixture::<Tv>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                template: Some("{number} {channel}".parse().unwrap()),
+                ..fixed_options()
+            },
+        );
+        assert_eq!(render(printer), "1 NHK総合\n4 日テレ\n");
+    }
+
+    #[test]
+    fn test_sort_works() {
+        let program = |channel: &str, start: Option<&str>| Program {
+            channel: channel.to_string(),
+            number: None,
+            name: channel.to_string(),
+            start: start.and_then(|s| NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M").ok()),
+            end: None,
+            title: "番組".to_string(),
+            detail: None,
+        };
+        let mut programs = vec![
+            program("[1] NHK総合", Some("202401012100")),
+            program("[1] NHK総合", None),
+            program("[2] Eテレ", Some("202401012000")),
+            program("[4] 日テレ", Some("202401012100")),
+        ];
+        sort_programs(&mut programs);
+        assert_eq!(
+            programs,
+            vec![
+                program("[2] Eテレ", Some("202401012000")),
+                program("[1] NHK総合", Some("202401012100")),
+                program("[4] 日テレ", Some("202401012100")),
+                program("[1] NHK総合", None),
+            ]
+        );
+
+        let printer = from_fixture::<TodayTv>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                sort: SortOrder::Time,
+                ..fixed_options()
+            },
+        );
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合 20:00 ~ 21:00 大河ドラマ\n[2] Eテレ 20:00 ~ 20:30 0655\n"
+        );
+    }
+
+    #[test]
+    fn test_format_works() {
+        let printer = |format| {
+            from_fixture::<TodayTv>(
+                ChannelSource::Area(42),
+                CURRENT,
+                Options {
+                    format,
+                    ..fixed_options()
+                },
+            )
+        };
+        let json: serde_json::Value = serde_json::from_str(&render(printer(Format::Json))).unwrap();
+        assert_eq!(json[0]["channel"], "[1] NHK総合");
+        assert_eq!(json[0]["start"], "2024-01-01T20:00:00");
+        assert_eq!(json[1]["title"], "0655");
+        assert_eq!(json.as_array().unwrap().len(), 2);
+
+        let ndjson = render(printer(Format::Ndjson));
+        let lines = ndjson.lines().collect::<Vec<_>>();
+        assert_eq!(lines.len(), 2);
+        let first: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["channel"], "[1] NHK総合");
+        assert_eq!(first["end"], "2024-01-01T21:00:00");
+        let second: serde_json::Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second["title"], "0655");
+
+        assert_eq!(
+            render(printer(Format::Csv)),
+            "channel,start,end,title\n\
+             [1] NHK総合,2024-01-01T20:00:00,2024-01-01T21:00:00,大河ドラマ\n\
+             [2] Eテレ,2024-01-01T20:00:00,2024-01-01T20:30:00,0655\n"
+        );
+        let printer_utc = |format| {
+            from_fixture::<TodayTv>(
+                ChannelSource::Area(42),
+                CURRENT,
+                Options {
+                    format,
+                    utc: true,
+                    ..fixed_options()
+                },
+            )
+        };
+        let json: serde_json::Value =
+            serde_json::from_str(&render(printer_utc(Format::Json))).unwrap();
+        assert_eq!(json[0]["start"], "2024-01-01T11:00:00Z");
+        assert_eq!(json[0]["end"], "2024-01-01T12:00:00Z");
+        assert!(render(printer_utc(Format::Csv))
+            .contains("[1] NHK総合,2024-01-01T11:00:00Z,2024-01-01T12:00:00Z,大河ドラマ\n"));
+        assert!(render(printer_utc(Format::Ical))
+            .contains("DTSTART:20240101T110000Z\r\nDTEND:20240101T120000Z\r\n"));
+
+        assert_eq!(
+            render(printer(Format::Tsv)),
+            "channel\tstart\tend\ttitle\n\
+             [1] NHK総合\t2024-01-01T20:00:00\t2024-01-01T21:00:00\t大河ドラマ\n\
+             [2] Eテレ\t2024-01-01T20:00:00\t2024-01-01T20:30:00\t0655\n"
+        );
+        assert_eq!(
+            render(printer(Format::Markdown)),
+            "| channel | start | end | title |\n\
+             | --- | --- | --- | --- |\n\
+             | [1] NHK総合 | 01/01 20:00 | 01/01 21:00 | 大河ドラマ |\n\
+             | [2] Eテレ | 01/01 20:00 | 01/01 20:30 | 0655 |\n"
+        );
+        let ical = render(printer(Format::Ical));
+        assert!(ical.starts_with("BEGIN:VCALENDAR\r\n"));
+        assert!(ical.contains(
+            "DTSTART:20240101T200000\r\nDTEND:20240101T210000\r\nSUMMARY:大河ドラマ\r\n"
+        ));
+        assert_eq!(ical.matches("BEGIN:VEVENT").count(), 2);
+        assert!(ical.ends_with("END:VCALENDAR\r\n"));
+
+        let printer = from_fixture::<Tv>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                format: Format::Csv,
+                ..fixed_options()
+            },
+        );
+        assert_eq!(
+            render(printer),
+            "channel,start,end,title\n\
+             [1] NHK総合,2024-01-01T19:00:00,2024-01-01T20:00:00,クローズアップ現代\n\
+             [4] 日テレ,2024-01-01T18:56:00,2024-01-01T20:54:00,映画&トーク\n"
+        );
+    }
+    #[test]
+    fn test_format_escape_works() {
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(tsv_field("a\tb\nc"), "a\\tb\\nc");
+        assert_eq!(tsv_field("C:\\tv"), "C:\\\\tv");
+        assert_eq!(markdown_cell("a|b"), "a\\|b");
+        assert_eq!(ical_text("a;b,c\nd"), "a\\;b\\,c\\nd");
+    }
+
+    #[test]
+    fn test_detail_works() {
+        let printer = from_fixture::<TodayTv>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                detail: true,
+                ..fixed_options()
+            },
+        );
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合\n20:00 ~ 21:00 大河ドラマ\n    第1回「旅立ち」\n\
+             [2] Eテレ\n20:00 ~ 20:30 0655\n[4] 日テレ\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_synopsis_works() {
+        assert_eq!(
+            parse_synopsis(DETAIL),
+            Some("今夜のテーマは\"旅\"。".to_string())
+        );
+        assert_eq!(parse_synopsis(DETAIL_EMPTY), None);
+        assert_eq!(parse_synopsis(""), None);
+    }
+
+    #[test]
+    fn test_detail_urls_works() {
+        let doc = fixture(DETAIL_LINKS);
+        let options = fixed_options();
+        assert_eq!(
+            detail_urls(&doc, &selectors().current, &options),
+            [
+                "https://bangumi.org/tv_events/AAAA",
+                "https://example.com/tv_events/CCCC"
+            ]
+        );
+        // 番組表に概要がある番組とリンクの無い番組は除き、同じページは一度だけ
+        assert_eq!(
+            detail_urls(&doc, &selectors().future, &options),
+            ["https://bangumi.org/tv_events/AAAA"]
+        );
+    }
+
+    #[test]
+    fn test_fetched_detail_works() {
+        let options = Options {
+            detail: true,
+            base_url: "http://detail.test/".to_string(),
+            ..fixed_options()
+        };
+        DETAILS.lock().unwrap().insert(
+            "http://detail.test/tv_events/AAAA".to_string(),
+            "取得した概要".to_string(),
+        );
+        // 取得できなかった番組は概要なしで表示する
+        let printer = from_fixture::<TodayTv>(ChannelSource::Area(42), DETAIL_LINKS, options);
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合\n20:00 ~ 21:00 大河ドラマ\n    第1回「旅立ち」\n\
+             21:00 ~ 22:00 クローズアップ現代(再)\n    取得した概要\n\
+             [4] 日テレ\n20:54 ~ 21:00 天気\n"
+        );
+    }
+
+    #[test]
+    fn test_summary_works() {
+        let printer = WeekTv::new(
+            &[CURRENT, EMPTY_TITLE],
+            &Options {
+                summary: true,
+                ..fixed_options()
+            },
+        )
+        .unwrap();
+        let output = render(printer);
+        let summary = output.split("\n\n").nth(1).unwrap();
+        assert_eq!(
+            summary,
+            "[1] NHK総合 2番組 2時間00分\n[2] Eテレ 2番組 1時間00分\n[4] 日テレ 0番組 0時間00分\n"
+        );
+    }
+
+    #[test]
+    fn test_week_tv_print_works() {
+        let printer = WeekTv::new(&[CURRENT, EMPTY_TITLE], &fixed_options()).unwrap();
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合 Mon 20:00 ~ Mon 21:00 大河ドラマ\n\
+             [2] Eテレ Mon 20:00 ~ Mon 20:30 0655\n\
+             [1] NHK総合 Mon 05:00 ~ Mon 06:00 ニュース\n\
+             [2] Eテレ Mon 05:30 ~ Mon 06:00 0655\n"
+        );
+    }
+
+    #[test]
+    fn test_time_grid_works() {
+        let program = |channel: &str, start: &str, title: &str| Program {
+            channel: channel.to_string(),
+            number: None,
+            name: channel.to_string(),
+            start: NaiveDateTime::parse_from_str(start, "%Y%m%d%H%M").ok(),
+            end: None,
+            title: title.to_string(),
+            detail: None,
+        };
+        let channels = vec!["[1] NHK総合".to_string(), "[2] Eテレ".to_string()];
+        let programs = vec![
+            program("[1] NHK総合", "202401011900", "ニュース7"),
+            program("[1] NHK総合", "202401012000", "大河ドラマ"),
+            program("[2] Eテレ", "202401011900", "0655"),
+            program("[2] Eテレ", "202401011930", "きょうの料理"),
+        ];
+        assert_eq!(
+            time_grid(&channels, &programs, 30, "…"),
+            vec![
+                "      [1] NHK総合 [2] Eテレ",
+                "19:00 ニュース7   0655/きょ…",
+                "20:00 大河ドラマ",
+            ]
+        );
+    }
+    #[test]
+    fn test_week_group_by_time_works() {
+        let now = Local.with_ymd_and_hms(2024, 1, 1, 19, 30, 0).unwrap();
+        let options = Options {
+            clock: Arc::new(FixedClock(now)),
+            group_by_time: true,
+            ..Options::default()
+        };
+        let printer = WeekTv::new(&[BOUNDARY_DAY1, BOUNDARY_DAY2], &options).unwrap();
+        assert_eq!(
+            render(printer).lines().collect::<Vec<_>>(),
+            vec![
+                "2024-01-01(月)",
+                "      [1] NHK総合",
+                "23:00 ニュース",
+                "04:00 映画",
+                "",
+                "2024-01-02(火)",
+                "      [1] NHK総合",
+                "05:00 おはよう日本",
+            ]
+        );
+        // `--since`より前の番組だけの日は表にしない
+        let since = NaiveDateTime::parse_from_str("202401020500", "%Y%m%d%H%M").ok();
+        let printer = WeekTv::new(
+            &[BOUNDARY_DAY1, BOUNDARY_DAY2],
+            &Options {
+                since,
+                ..options.clone()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            render(printer).lines().collect::<Vec<_>>(),
+            vec!["2024-01-02(火)", "      [1] NHK総合", "05:00 おはよう日本"]
+        );
+        let empty: [&str; 0] = [];
+        assert!(matches!(
+            WeekTv::new(&empty, &options),
+            Err(TvnowError::Structure)
+        ));
+    }
+
+    #[test]
+    fn test_paginate_works() {
+        let program = |title: &str| Program {
+            channel: "[1] NHK総合".to_string(),
+            number: Some("1".to_string()),
+            name: "NHK総合".to_string(),
+            start: None,
+            end: None,
+            title: title.to_string(),
+            detail: None,
+        };
+        let programs = ["a", "b", "c", "d", "e"].map(program).to_vec();
+        let page = |number| Page { number, size: 2 };
+        assert_eq!(
+            paginate(programs.clone(), page(1)),
+            (vec![program("a"), program("b")], 3)
+        );
+        assert_eq!(paginate(programs.clone(), page(3)), (vec![program("e")], 3));
+        assert_eq!(paginate(programs, page(4)), (vec![], 3));
+        assert_eq!(paginate(vec![], page(1)), (vec![], 1));
+    }
+    #[test]
+    fn test_merge_programs_works() {
+        let program = |start: &str, end: &str, title: &str| Program {
+            channel: "[1] NHK総合".to_string(),
+            number: Some("1".to_string()),
+            name: "NHK総合".to_string(),
+            start: NaiveDateTime::parse_from_str(start, "%Y%m%d%H%M").ok(),
+            end: NaiveDateTime::parse_from_str(end, "%Y%m%d%H%M").ok(),
+            title: title.to_string(),
+            detail: None,
+        };
+        let programs = vec![
+            program("202401011900", "202401012000", "野球中継"),
+            program("202401012000", "202401012054", "野球中継"),
+            program("202401012054", "202401012100", "ニュース"),
+            program("202401012100", "202401012200", "野球中継"),
+            program("202401012300", "202401012330", "野球中継"),
+        ];
+        assert_eq!(
+            merge_programs(programs),
+            vec![
+                program("202401011900", "202401012054", "野球中継"),
+                program("202401012054", "202401012100", "ニュース"),
+                program("202401012100", "202401012200", "野球中継"),
+                program("202401012300", "202401012330", "野球中継"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_bs_tv_print_works() {
+        let printer = from_fixture::<Tv>(ChannelSource::Bs, BS, fixed_options());
+        assert_eq!(
+            render(printer),
+            "[141] BS日テレ 深層NEWS\n[151] BS朝日 現在放送していません\n"
+        );
+    }
+
+    #[test]
+    fn test_today_bs_tv_print_works() {
+        let printer = from_fixture::<TodayTv>(ChannelSource::Bs, BS, fixed_options());
+        assert_eq!(
+            render(printer),
+            "[141] BS日テレ\n21:00 ~ 22:00 ドラマ\n[151] BS朝日\n21:00 ~ 21:54 世界の車窓から\n"
+        );
+    }
+
+    #[test]
+    fn test_week_bs_tv_print_works() {
+        let printer = WeekTv::new(&[BS], &fixed_options()).unwrap();
+        assert_eq!(
+            render(printer),
+            "[141] BS日テレ Mon 21:00 ~ Mon 22:00 ドラマ\n\
+             [151] BS朝日 Mon 21:00 ~ Mon 21:54 世界の車窓から\n"
+        );
+    }
+
+    #[test]
+    fn test_ascii_works() {
+        let printer = WeekTv::new(
+            &[BS],
+            &Options {
+                ascii: true,
+                ..fixed_options()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            render(printer),
+            "[141] BS日テレ Mon 21:00 -> Mon 22:00 ドラマ\n\
+             [151] BS朝日 Mon 21:00 -> Mon 21:54 世界の車窓から\n"
+        );
+    }
+
+    #[test]
+    fn test_flat_works() {
+        let options = Options {
+            flat: true,
+            sort: SortOrder::Time,
+            ..fixed_options()
+        };
+        let printer = WeekTv::new(&[CURRENT, BS], &options).unwrap();
+        assert_eq!(
+            render(printer),
+            "2024-01-01(月) 20:00-21:00 [1] NHK総合 大河ドラマ\n\
+             2024-01-01(月) 20:00-20:30 [2] Eテレ 0655\n\
+             2024-01-01(月) 21:00-22:00 [141] BS日テレ ドラマ\n\
+             2024-01-01(月) 21:00-21:54 [151] BS朝日 世界の車窓から\n"
+        );
+    }
+
+    #[test]
+    fn test_hide_offair_works() {
+        let printer = from_fixture::<Tv>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                hide_offair: true,
+                ..fixed_options()
+            },
+        );
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合 クローズアップ現代\n[4] 日テレ 映画&トーク\n"
+        );
+    }
+
+    #[test]
+    fn test_off_air_placeholder_works() {
+        let tv = |placeholder: Option<&str>, compact| {
+            from_fixture::<Tv>(
+                ChannelSource::Area(42),
+                CURRENT,
+                Options {
+                    off_air_placeholder: placeholder.map(String::from),
+                    compact,
+                    lang: Lang::En,
+                    ..fixed_options()
+                },
+            )
+        };
+        let line = |placeholder, compact| {
+            render(tv(placeholder, compact))
+                .lines()
+                .find(|line| line.contains("Eテレ"))
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(line(None, false), "[2] Eテレ Not currently broadcasting");
+        assert_eq!(line(Some("off"), false), "[2] Eテレ off");
+        assert_eq!(line(Some(""), false), "[2] Eテレ");
+        assert!(line(Some("off"), true).contains("Eテレ:off"));
+    }
+
+    #[test]
+    fn test_lang_works() {
+        let printer = from_fixture::<Tv>(
+            ChannelSource::Bs,
+            BS,
+            Options {
+                lang: Lang::En,
+                ..fixed_options()
+            },
+        );
+        assert_eq!(
+            render(printer),
+            "[141] BS日テレ 深層NEWS\n[151] BS朝日 Not currently broadcasting\n"
+        );
+    }
+
+    #[test]
+    fn test_search_works() {
+        let printer = from_fixture::<TodayTv>(
+            ChannelSource::Bs,
+            BS,
+            Options {
+                search: Some(Regex::new("車窓").unwrap()),
+                ..fixed_options()
+            },
+        );
+        assert_eq!(
+            render(printer),
+            "[141] BS日テレ\n[151] BS朝日\n21:00 ~ 21:54 世界の車窓から\n"
+        );
+
+        let printer = from_fixture::<Tv>(
+            ChannelSource::Bs,
+            BS,
+            Options {
+                search: Some(Regex::new(r"NEWS$").unwrap()),
+                ..fixed_options()
+            },
+        );
+        assert_eq!(render(printer), "[141] BS日テレ 深層NEWS\n");
+    }
+
+    #[test]
+    fn test_compact_works() {
+        let printer = from_fixture::<Tv>(
+            ChannelSource::Area(42),
+            CURRENT,
+            Options {
+                compact: true,
+                ..fixed_options()
+            },
+        );
+        assert_eq!(
+            render(printer),
+            "NHK総合:クローズアップ現代 | Eテレ:- | 日テレ:映画&トーク\n"
+        );
+    }
+
+    #[test]
+    fn test_wrap_compact_works() {
+        let items = vec![
+            (9, "NHK総合:-".to_string()),
+            (7, "Eテレ:-".to_string()),
+            (8, "日テレ:-".to_string()),
+        ];
+        assert_eq!(
+            wrap_compact(&items, None),
+            vec!["NHK総合:- | Eテレ:- | 日テレ:-"]
+        );
+        assert_eq!(
+            wrap_compact(&items, Some(20)),
+            vec!["NHK総合:- | Eテレ:-", "日テレ:-"]
+        );
+        assert_eq!(
+            wrap_compact(&items, Some(5)),
+            vec!["NHK総合:-", "Eテレ:-", "日テレ:-"]
+        );
+    }
+    #[test]
+    fn test_wrap_text_works() {
+        assert_eq!(
+            wrap_text("大河ドラマ", 14, 20),
+            "大河ド\n              ラマ"
+        );
+        assert_eq!(wrap_text("News7", 4, 7), "New\n    s7");
+        assert_eq!(
+            wrap_text("\x1b[31mNews\x1b[0m", 4, 7),
+            "\x1b[31mNew\n    s\x1b[0m"
+        );
+        assert_eq!(wrap_text("大河ドラマ", 14, 40), "大河ドラマ");
+        assert_eq!(wrap_text("大河ドラマ", 20, 20), "大河ドラマ");
+    }
+    #[test]
+    fn test_display_width_works() {
+        assert_eq!(display_width("大河ドラマ"), 10);
+        assert_eq!(display_width("\x1b[1;31mNews7\x1b[0m"), 5);
+    }
+    #[test]
+    fn test_truncate_text_works() {
+        assert_eq!(truncate_text("大河ドラマ", 14, 24, "…"), "大河ドラマ");
+        assert_eq!(truncate_text("大河ドラマ", 14, 23, "…"), "大河ドラ…");
+        assert_eq!(truncate_text("大河ドラマ", 14, 22, "…"), "大河ド…");
+        assert_eq!(truncate_text("News7", 0, 4, "…"), "New…");
+        assert_eq!(truncate_text("News7", 0, 4, "..."), "N...");
+        assert_eq!(
+            truncate_text("\x1b[31mNews7\x1b[0m", 0, 4, "…"),
+            "\x1b[31mNew…\x1b[0m"
+        );
+    }
+    #[test]
+    fn test_contains_ignore_case_works() {
+        assert!(contains_ignore_case("NHKニュース", "nhk"));
+        assert!(contains_ignore_case("ドラマ Special", "SPECIAL"));
+        assert!(!contains_ignore_case("天気", "nhk"));
+    }
+
+    #[test]
+    fn test_upcoming_programs_works() {
+        let doc = fixture(CURRENT);
+        let options = Options::default();
+        let at = |h, m| {
+            NaiveDate::from_ymd_opt(2024, 1, 1)
+                .unwrap()
+                .and_hms_opt(h, m, 0)
+                .unwrap()
+        };
+        let lead = Duration::minutes(5);
+        let upcoming = upcoming_programs(&doc, &options, at(19, 56), lead);
+        assert_eq!(
+            upcoming,
+            vec![
+                Upcoming {
+                    channel: "NHK総合".to_string(),
+                    start: at(20, 0),
+                    title: "大河ドラマ".to_string(),
+                },
+                Upcoming {
+                    channel: "Eテレ".to_string(),
+                    start: at(20, 0),
+                    title: "0655".to_string(),
+                },
+            ]
+        );
+        assert!(upcoming_programs(&doc, &options, at(19, 50), lead).is_empty());
+        assert!(upcoming_programs(&doc, &options, at(20, 1), lead).is_empty());
+
+        let options = Options {
+            search: Some(Regex::new("大河").unwrap()),
+            ..Options::default()
+        };
+        let upcoming = upcoming_programs(&doc, &options, at(19, 56), lead);
+        assert_eq!(upcoming.len(), 1);
+        assert_eq!(upcoming[0].title, "大河ドラマ");
+    }
+    #[test]
+    fn test_check_channel_names_works() {
+        let options = |names: &[&str], check| Options {
+            channel_names: names.iter().map(|n| n.to_string()).collect(),
+            check_channel_names: check,
+            ..fixed_options()
+        };
+        let doc = fixture(CURRENT);
+        assert!(check_channel_names(&doc, &options(&["nhk", "テレ"], true)).is_ok());
+        assert!(matches!(
+            check_channel_names(&doc, &options(&["nhk", "hogehoge"], true)),
+            Err(TvnowError::UnknownChannel { name, .. }) if name == "hogehoge"
+        ));
+        assert!(check_channel_names(&doc, &options(&["hogehoge"], false)).is_ok());
+        assert!(matches!(
+            TodayTv::from_html::<Vec<u8>>(
+                ChannelSource::Area(42),
+                CURRENT,
+                &options(&["hogehoge"], true)
+            ),
+            Err(TvnowError::UnknownChannel { .. })
+        ));
+    }
+    #[test]
+    fn test_check_strict_works() {
+        let strict = Options {
+            strict: true,
+            ..Options::default()
+        };
+        assert!(check_strict(&fixture(CURRENT), &strict).is_ok());
+        assert!(check_strict(&fixture(EMPTY_TITLE), &Options::default()).is_ok());
+        assert!(matches!(
+            check_strict(&fixture(EMPTY_TITLE), &strict),
+            Err(TvnowError::Strict(_))
+        ));
+        assert!(matches!(
+            check_strict(&fixture("<html></html>"), &strict),
+            Err(TvnowError::Strict(_))
+        ));
+    }
+    #[test]
+    fn test_decode_body_works() {
+        use flate2::write::{GzEncoder, ZlibEncoder};
+        use flate2::Compression;
+
+        let mut gzip = GzEncoder::new(vec![], Compression::default());
+        gzip.write_all(CURRENT.as_bytes()).unwrap();
+        let gzip = gzip.finish().unwrap();
+        assert!(gzip.len() < CURRENT.len());
+        assert_eq!(
+            decompress_body(gzip, Some("gzip")).unwrap(),
+            CURRENT.as_bytes()
+        );
+
+        let mut deflate = ZlibEncoder::new(vec![], Compression::default());
+        deflate.write_all(CURRENT.as_bytes()).unwrap();
+        let deflate = deflate.finish().unwrap();
+        assert_eq!(
+            decompress_body(deflate, Some("deflate")).unwrap(),
+            CURRENT.as_bytes()
+        );
+
+        let identity = CURRENT.as_bytes().to_vec();
+        assert_eq!(decompress_body(identity, None).unwrap(), CURRENT.as_bytes());
+        assert!(matches!(
+            decompress_body(b"not gzip".to_vec(), Some("gzip")),
+            Err(TvnowError::Decode(_))
+        ));
+    }
+    #[test]
+    fn test_decode_text_works() {
+        let utf8 = "<meta charset=\"utf-8\">番組表".as_bytes();
+        assert_eq!(
+            decode_text(utf8, None).unwrap(),
+            "<meta charset=\"utf-8\">番組表"
+        );
+        assert_eq!(
+            decode_text("番組表".as_bytes(), Some("text/html; charset=UTF-8")).unwrap(),
+            "番組表"
+        );
+
+        let (sjis, _, _) = encoding_rs::SHIFT_JIS.encode("番組表");
+        assert_eq!(
+            decode_text(&sjis, Some("text/html; charset=Shift_JIS")).unwrap(),
+            "番組表"
+        );
+        let mut html =
+            b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=shift_jis\">".to_vec();
+        html.extend_from_slice(&sjis);
+        assert!(decode_text(&html, None).unwrap().ends_with("番組表"));
+
+        assert!(matches!(
+            decode_text(&sjis, Some("text/html; charset=utf-8")),
+            Err(TvnowError::Decode(_))
+        ));
+        assert!(matches!(
+            decode_text(b"", Some("text/html; charset=klingon")),
+            Err(TvnowError::Decode(_))
+        ));
+    }
+    #[test]
+    fn test_check_selectors_works() {
+        assert!(check_selectors().is_ok());
+    }
+    #[test]
+    fn test_parse_document_works() {
+        assert!(parse_document(CURRENT).is_ok());
+        let result = parse_document(
+            r#"<div id="ch_area"><ul><li class="topmost"><p>1 NHK総合</p></li></ul></div>
+            <div id="program_area"><ul></ul><ul></ul></div>"#,
+        );
+        assert!(matches!(result, Err(TvnowError::Parse(_))));
+        assert!(matches!(
+            parse_document("<html></html>"),
+            Err(TvnowError::Structure)
+        ));
+        assert!(matches!(
+            parse_document(
+                r#"<div id="ch_area"><ul><li class="topmost"><p>1 NHK総合</p></li></ul></div>"#
+            ),
+            Err(TvnowError::Structure)
+        ));
+    }
+
+    #[test]
+    fn test_remaining_minutes_works() {
+        let now = NaiveDateTime::parse_from_str("202401011948", "%Y%m%d%H%M").unwrap();
+        assert_eq!(remaining_minutes("202401012000", now), Some(12));
+        assert_eq!(remaining_minutes("202401012130", now), Some(102));
+        assert_eq!(remaining_minutes("202401011948", now), None);
+        assert_eq!(remaining_minutes("202401011900", now), None);
+        assert_eq!(remaining_minutes("invalid", now), None);
+    }
+
+    #[test]
+    fn test_broadcast_date_works() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let now = Local.with_ymd_and_hms(2024, 1, 1, 4, 59, 0).unwrap();
+        assert_eq!(broadcast_date(now, 5), date - Duration::days(1));
+        let now = Local.with_ymd_and_hms(2024, 1, 1, 5, 0, 0).unwrap();
+        assert_eq!(broadcast_date(now, 5), date);
+        let now = Local.with_ymd_and_hms(2024, 1, 1, 0, 30, 0).unwrap();
+        assert_eq!(broadcast_date(now, 0), date);
+    }
+
+    #[test]
+    fn test_today_url_works() {
+        let source = ChannelSource::Area(42);
+        let at = |day, hour, day_offset| Options {
+            day_offset,
+            clock: Arc::new(FixedClock(
+                Local.with_ymd_and_hms(2024, 1, day, hour, 0, 0).unwrap(),
+            )),
+            ..Options::default()
+        };
+        assert_eq!(
+            TodayTv::urls(source, &at(2, 2, 0)),
+            vec!["https://bangumi.org/epg/td?broad_cast_date=20240101&ggm_group_id=42"]
+        );
+        assert_eq!(
+            TodayTv::urls(source, &at(2, 12, 0)),
+            vec!["https://bangumi.org/epg/td?ggm_group_id=42"]
+        );
+        assert_eq!(
+            TodayTv::urls(source, &at(2, 12, 1)),
+            vec!["https://bangumi.org/epg/td?broad_cast_date=20240103&ggm_group_id=42"]
+        );
+    }
+
+    #[test]
+    fn test_week_urls_works() {
+        let at = |hour, minute| Options {
+            clock: Arc::new(FixedClock(
+                Local.with_ymd_and_hms(2024, 1, 2, hour, minute, 0).unwrap(),
+            )),
+            ..Options::default()
+        };
+        let urls = WeekTv::urls(ChannelSource::Bs, &at(4, 59));
+        assert_eq!(
+            urls[0],
+            "https://bangumi.org/epg/bs?broad_cast_date=20240101"
+        );
+        assert_eq!(
+            urls[7],
+            "https://bangumi.org/epg/bs?broad_cast_date=20240108"
+        );
+        let urls = WeekTv::urls(ChannelSource::Bs, &at(5, 0));
+        assert_eq!(
+            urls[0],
+            "https://bangumi.org/epg/bs?broad_cast_date=20240102"
+        );
+        assert_eq!(
+            urls[7],
+            "https://bangumi.org/epg/bs?broad_cast_date=20240109"
+        );
+    }
+
+    #[test]
+    fn test_guide_time_works() {
+        let date = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let at = |s| NaiveDateTime::parse_from_str(s, "%Y%m%d%H%M").ok();
+        assert_eq!(guide_time(at("202401012354"), date), "23:54");
+        assert_eq!(guide_time(at("202401020000"), date), "24:00");
+        assert_eq!(guide_time(at("202401020130"), date), "25:30");
+        assert_eq!(guide_time(None, date), "--:--");
+    }
+
+    #[test]
+    fn test_request_error_works() {
+        let err = surf::Error::new(
+            surf::StatusCode::InternalServerError,
+            io::Error::new(io::ErrorKind::ConnectionRefused, "refused"),
+        );
+        assert!(matches!(request_error(&err), TvnowError::Offline(_)));
+        let err = surf::Error::from_str(
+            surf::StatusCode::InternalServerError,
+            "failed to lookup address information: Name or service not known",
+        );
+        assert!(matches!(request_error(&err), TvnowError::Offline(_)));
+        let err = surf::Error::from_str(
+            surf::StatusCode::InternalServerError,
+            "connect: No route to host (os error 113)",
+        );
+        assert!(matches!(request_error(&err), TvnowError::Offline(_)));
+        let err = surf::Error::from_str(surf::StatusCode::InternalServerError, "broken pipe");
+        assert!(matches!(request_error(&err), TvnowError::Http(_)));
+        // 元のエラーではなく短いメッセージを表示する
+        assert_eq!(
+            TvnowError::Offline("no such host".to_string()).to_string(),
+            "could not reach bangumi.org — are you online?"
+        );
+    }
+
+    #[test]
+    fn test_retry_with_jitter_works() {
+        let calls = Cell::new(0);
+        let fetch = |failures: usize, err: TvnowError| {
+            calls.set(0);
+            task::block_on(retry_with_jitter(
+                "https://example.com",
+                std::time::Duration::ZERO,
+                || {
+                    calls.set(calls.get() + 1);
+                    let result = if calls.get() <= failures {
+                        Err(err.clone())
+                    } else {
+                        Ok("body".to_string())
+                    };
+                    async move { result }
+                },
+            ))
+        };
+        // 失敗した後に成功する
+        assert_eq!(fetch(2, TvnowError::Timeout).unwrap(), "body");
+        assert_eq!(calls.get(), 3);
+        // やり直しの回数を超えたら諦める
+        assert!(matches!(
+            fetch(3, TvnowError::BadStatus(503)),
+            Err(TvnowError::BadStatus(503))
+        ));
+        assert_eq!(calls.get(), 3);
+        // 一時的でないエラーはやり直さない
+        assert!(matches!(
+            fetch(1, TvnowError::BadStatus(404)),
+            Err(TvnowError::BadStatus(404))
+        ));
+        assert_eq!(calls.get(), 1);
+        // ネットワークに繋がっていなければすぐに諦める
+        assert!(matches!(
+            fetch(1, TvnowError::Offline("no such host".to_string())),
+            Err(TvnowError::Offline(_))
+        ));
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn test_backoff_delay_works() {
+        let base = std::time::Duration::from_millis(100);
+        let ms = std::time::Duration::from_millis;
+        assert_eq!(backoff_delay(base, 0, 0.0), ms(50));
+        assert_eq!(backoff_delay(base, 0, 0.5), ms(75));
+        assert_eq!(backoff_delay(base, 2, 0.0), ms(200));
+        for _ in 0..100 {
+            let jitter = jitter();
+            assert!((0.0..1.0).contains(&jitter));
+        }
+    }
+
+    #[test]
+    fn test_has_started_works() {
+        let now = NaiveDateTime::parse_from_str("202401012000", "%Y%m%d%H%M").unwrap();
+        assert!(has_started("202401011959", now));
+        assert!(!has_started("202401012000", now));
+        assert!(!has_started("invalid", now));
+    }
+
+    #[test]
+    fn test_empty_title_is_skipped() {
+        let printer =
+            from_fixture::<TodayTv>(ChannelSource::Area(42), EMPTY_TITLE, fixed_options());
+        assert_eq!(
+            render(printer),
+            "[1] NHK総合\n05:00 ~ 06:00 ニュース\n[2] Eテレ\n05:30 ~ 06:00 0655\n"
+        );
+    }
 }
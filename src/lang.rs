@@ -0,0 +1,152 @@
+use chrono::Weekday;
+use std::env;
+use std::str::FromStr;
+
+/// 表示言語
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum Lang {
+    #[default]
+    Ja,
+    En,
+}
+
+impl Lang {
+    /// ロケールの環境変数から表示言語を決める
+    /// 日本語のロケールなら日本語、それ以外や未設定の場合は元々のメッセージの英語
+    pub fn from_env() -> Self {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|key| env::var(key).ok())
+            .find(|value| !value.is_empty())
+            .map(|value| {
+                if value.starts_with("ja") {
+                    Lang::Ja
+                } else {
+                    Lang::En
+                }
+            })
+            .unwrap_or(Lang::En)
+    }
+
+    pub fn off_air(&self) -> &'static str {
+        match self {
+            Lang::Ja => "現在放送していません",
+            Lang::En => "Not currently broadcasting",
+        }
+    }
+
+    pub fn not_in_area(&self, area: &str) -> String {
+        match self {
+            Lang::Ja => format!("{} はエリアにありません", area),
+            Lang::En => format!("{} is not in the area", area),
+        }
+    }
+
+    pub fn unknown_channel(&self, channel: &str) -> String {
+        match self {
+            Lang::Ja => format!("{} を含むチャンネルはありません", channel),
+            Lang::En => format!("No channel name contains {}", channel),
+        }
+    }
+
+    pub fn config_dir_not_found(&self) -> &'static str {
+        match self {
+            Lang::Ja => "設定ディレクトリが見つかりません",
+            Lang::En => "config directory not found",
+        }
+    }
+
+    pub fn cache_dir_not_found(&self) -> &'static str {
+        match self {
+            Lang::Ja => "キャッシュディレクトリが見つかりません",
+            Lang::En => "cache directory not found",
+        }
+    }
+
+    pub fn utc_requires_format(&self) -> &'static str {
+        match self {
+            Lang::Ja => "--utc には --format か --ndjson が必要です",
+            Lang::En => "--utc requires --format or --ndjson",
+        }
+    }
+
+    pub fn no_stdin_area(&self) -> &'static str {
+        match self {
+            Lang::Ja => "標準入力にエリア名がありません",
+            Lang::En => "no area name on stdin",
+        }
+    }
+
+    pub fn remaining(&self, minutes: i64) -> String {
+        match self {
+            Lang::Ja => format!("(あと{}分)", minutes),
+            Lang::En => format!("({}m left)", minutes),
+        }
+    }
+
+    pub fn summary(&self, count: usize, hours: i64, minutes: i64) -> String {
+        match self {
+            Lang::Ja => format!("{}番組 {}時間{:02}分", count, hours, minutes),
+            Lang::En => format!("{} programs {}h{:02}m", count, hours, minutes),
+        }
+    }
+
+    pub fn no_programs(&self) -> &'static str {
+        match self {
+            Lang::Ja => "番組がありません",
+            Lang::En => "No programs found",
+        }
+    }
+
+    pub fn fetching(&self, done: usize, total: usize) -> String {
+        match self {
+            Lang::Ja => format!("取得中 {}/{}…", done, total),
+            Lang::En => format!("fetching {}/{}…", done, total),
+        }
+    }
+
+    pub fn started(&self) -> &'static str {
+        match self {
+            Lang::Ja => "(開始)",
+            Lang::En => "(new)",
+        }
+    }
+
+    pub fn weekday(&self, weekday: Weekday) -> &'static str {
+        match (self, weekday) {
+            (Lang::Ja, Weekday::Mon) => "月",
+            (Lang::Ja, Weekday::Tue) => "火",
+            (Lang::Ja, Weekday::Wed) => "水",
+            (Lang::Ja, Weekday::Thu) => "木",
+            (Lang::Ja, Weekday::Fri) => "金",
+            (Lang::Ja, Weekday::Sat) => "土",
+            (Lang::Ja, Weekday::Sun) => "日",
+            (Lang::En, Weekday::Mon) => "Mon",
+            (Lang::En, Weekday::Tue) => "Tue",
+            (Lang::En, Weekday::Wed) => "Wed",
+            (Lang::En, Weekday::Thu) => "Thu",
+            (Lang::En, Weekday::Fri) => "Fri",
+            (Lang::En, Weekday::Sat) => "Sat",
+            (Lang::En, Weekday::Sun) => "Sun",
+        }
+    }
+
+    pub fn page(&self, page: usize, pages: usize) -> String {
+        match self {
+            Lang::Ja => format!("{}/{}ページ", page, pages),
+            Lang::En => format!("page {}/{}", page, pages),
+        }
+    }
+}
+
+impl FromStr for Lang {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ja" => Ok(Lang::Ja),
+            "en" => Ok(Lang::En),
+            _ => Err(format!("{} is not a supported language (ja, en)", s)),
+        }
+    }
+}
@@ -1,4 +1,9 @@
+pub mod clock;
 mod cmd;
-mod epg;
+pub mod config;
+pub mod epg;
+pub mod lang;
+pub mod snapshot;
+pub mod theme;
 
-pub use cmd::Cli;
+pub use cmd::{Cli, CliBuilder, ExitCode, Mode, Output};
@@ -0,0 +1,103 @@
+use crate::config;
+use crate::epg::Program;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// `--diff`で比べる前回の現在放送中の番組
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Snapshot {
+    /// エリア名ごとの番組
+    pub areas: BTreeMap<String, Vec<Entry>>,
+}
+
+/// 前回と同じ番組かを比べるための項目
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Entry {
+    pub channel: String,
+    /// 開始時刻(`%Y%m%d%H%M`)
+    pub start: Option<String>,
+    pub title: String,
+}
+
+impl From<&Program> for Entry {
+    fn from(program: &Program) -> Self {
+        Entry {
+            channel: program.channel.clone(),
+            start: program
+                .start
+                .map(|start| start.format("%Y%m%d%H%M").to_string()),
+            title: program.title.clone(),
+        }
+    }
+}
+
+impl Snapshot {
+    /// 保存先
+    /// `$XDG_CACHE_HOME/tvnow/now.json`、`~/.cache/tvnow/now.json`の順
+    pub fn path() -> Option<PathBuf> {
+        let dir = env::var_os("XDG_CACHE_HOME")
+            .filter(|p| !p.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| config::home_dir().map(|home| home.join(".cache")))?;
+        Some(dir.join("tvnow").join("now.json"))
+    }
+
+    /// 前回の番組を読み込む
+    /// ファイルが無ければ空
+    pub fn load(path: &Path) -> io::Result<Self> {
+        match fs::read_to_string(path) {
+            Ok(text) => Ok(serde_json::from_str(&text)?),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Snapshot::default()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// 今回の番組を書き込む
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_string(self)? + "\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    #[test]
+    fn test_snapshot_round_trip_works() {
+        let dir = env::temp_dir().join(format!("tvnow-snapshot-{}", std::process::id()));
+        let path = dir.join("tvnow").join("now.json");
+        assert_eq!(Snapshot::load(&path).unwrap(), Snapshot::default());
+
+        let program = Program {
+            channel: "[1] NHK総合".to_string(),
+            number: Some("1".to_string()),
+            name: "NHK総合".to_string(),
+            start: NaiveDateTime::parse_from_str("202401011900", "%Y%m%d%H%M").ok(),
+            end: None,
+            title: "ニュース7".to_string(),
+            detail: None,
+        };
+        let mut snapshot = Snapshot::default();
+        snapshot
+            .areas
+            .insert("tokyo".to_string(), vec![Entry::from(&program)]);
+        snapshot.save(&path).unwrap();
+        let loaded = Snapshot::load(&path).unwrap();
+        assert_eq!(loaded, snapshot);
+        assert_eq!(
+            loaded.areas["tokyo"][0].start.as_deref(),
+            Some("202401011900")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
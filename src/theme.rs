@@ -0,0 +1,103 @@
+use crate::epg::ChannelSource;
+use colored::Color;
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::str::FromStr;
+
+/// 配色
+/// 色の指定はここにまとめる
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Theme {
+    /// 暗い背景の端末向け
+    #[default]
+    Dark,
+    /// 明るい背景の端末向け
+    Light,
+}
+
+impl Theme {
+    /// 端末の背景色を`COLORFGBG`から推測する
+    pub fn from_env() -> Option<Self> {
+        env::var("COLORFGBG")
+            .ok()
+            .and_then(|value| Self::from_colorfgbg(&value))
+    }
+
+    /// `15;0`のような`前景色;背景色`の背景色から決める
+    /// 背景色が7(白)か9以上の明るい色なら明るい背景
+    fn from_colorfgbg(value: &str) -> Option<Self> {
+        let background = value.rsplit(';').next()?.trim().parse::<u8>().ok()?;
+        match background {
+            7 | 9..=15 => Some(Theme::Light),
+            0..=6 | 8 => Some(Theme::Dark),
+            _ => None,
+        }
+    }
+
+    /// チャンネル名の色
+    pub fn channel(&self, source: ChannelSource) -> Color {
+        match (self, source) {
+            (Theme::Dark, ChannelSource::Area(_)) => Color::BrightYellow,
+            (Theme::Dark, ChannelSource::Bs) => Color::BrightCyan,
+            (Theme::Dark, ChannelSource::Cs) => Color::BrightMagenta,
+            (Theme::Light, ChannelSource::Area(_)) => Color::Blue,
+            (Theme::Light, ChannelSource::Bs) => Color::Magenta,
+            (Theme::Light, ChannelSource::Cs) => Color::Green,
+        }
+    }
+
+    /// 強調表示の色
+    /// 赤はどちらの背景でも読めるので共通
+    pub fn highlight(&self) -> Color {
+        Color::Red
+    }
+
+    /// エリア一覧のBS・CSとエラーメッセージのエリア名の色
+    pub fn area(&self) -> Color {
+        match self {
+            Theme::Dark => Color::BrightYellow,
+            Theme::Light => Color::Blue,
+        }
+    }
+}
+
+impl FromStr for Theme {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dark" => Ok(Theme::Dark),
+            "light" => Ok(Theme::Light),
+            _ => Err(format!("{} is not a theme (dark, light)", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_from_colorfgbg_works() {
+        assert_eq!(Theme::from_colorfgbg("15;0"), Some(Theme::Dark));
+        assert_eq!(Theme::from_colorfgbg("0;15"), Some(Theme::Light));
+        assert_eq!(Theme::from_colorfgbg("0;default;7"), Some(Theme::Light));
+        assert_eq!(Theme::from_colorfgbg("7;8"), Some(Theme::Dark));
+        assert_eq!(Theme::from_colorfgbg("default"), None);
+        assert_eq!(Theme::from_colorfgbg(""), None);
+    }
+
+    #[test]
+    fn test_palettes_differ() {
+        for source in [
+            ChannelSource::Area(42),
+            ChannelSource::Bs,
+            ChannelSource::Cs,
+        ] {
+            assert_ne!(Theme::Dark.channel(source), Theme::Light.channel(source));
+        }
+        assert_ne!(Theme::Dark.area(), Theme::Light.area());
+    }
+}
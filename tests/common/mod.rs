@@ -0,0 +1,31 @@
+// テストごとに使う項目が違う
+#![allow(dead_code)]
+
+use chrono::{Local, TimeZone};
+use colored::control::set_override;
+use std::sync::Arc;
+use tvnow::clock::FixedClock;
+use tvnow::epg::Options;
+
+pub const TODAY: &str = include_str!("../fixtures/today.html");
+pub const BS: &str = include_str!("../fixtures/bs.html");
+pub const EMPTY_TITLE: &str = include_str!("../fixtures/empty_title.html");
+pub const MALFORMED: &str = include_str!("../fixtures/malformed.html");
+pub const EMPTY_DAY: &str = include_str!("../fixtures/empty_day.html");
+
+/// 2024-01-01 19:30の番組表として扱う
+pub fn options() -> Options {
+    let now = Local.with_ymd_and_hms(2024, 1, 1, 19, 30, 0).unwrap();
+    Options {
+        clock: Arc::new(FixedClock(now)),
+        ..Options::default()
+    }
+}
+
+pub fn render<F: FnOnce(&mut Vec<u8>)>(print: F) -> String {
+    // カラー化無効
+    set_override(false);
+    let mut out = vec![];
+    print(&mut out);
+    String::from_utf8(out).unwrap()
+}
@@ -0,0 +1,199 @@
+mod common;
+
+use chrono::NaiveTime;
+use common::{options, render, BS, EMPTY_DAY, EMPTY_TITLE, MALFORMED, TODAY};
+use tvnow::epg::{
+    ChannelList, ChannelSource, Format, Options, Printer, TodayTv, Tv, TvnowError, WeekTv,
+};
+
+#[test]
+fn test_tv_from_fixture() {
+    let options = options();
+    let out = render(|out| {
+        Tv::from_html(ChannelSource::Area(42), TODAY, &options)
+            .unwrap()
+            .print(out)
+            .unwrap();
+    });
+    assert_eq!(
+        out,
+        "[1] NHK総合 ニュース7 (あと30分)\n\
+         [4] 日テレ 映画&トーク (あと84分)\n\
+         [6] TBS 日曜劇場 (あと90分)\n"
+    );
+}
+
+#[test]
+fn test_tv_ending_within_from_fixture() {
+    let out = |minutes| {
+        let options = Options {
+            ending_within: Some(minutes),
+            ..options()
+        };
+        render(|out| {
+            Tv::from_html(ChannelSource::Area(42), TODAY, &options)
+                .unwrap()
+                .print(out)
+                .unwrap();
+        })
+    };
+    assert_eq!(out(29), "");
+    assert_eq!(out(60), "[1] NHK総合 ニュース7 (あと30分)\n");
+    assert_eq!(
+        out(90),
+        "[1] NHK総合 ニュース7 (あと30分)\n\
+         [4] 日テレ 映画&トーク (あと84分)\n\
+         [6] TBS 日曜劇場 (あと90分)\n"
+    );
+}
+
+#[test]
+fn test_today_tv_from_fixture() {
+    let options = options();
+    let out = render(|out| {
+        TodayTv::from_html(ChannelSource::Area(42), TODAY, &options)
+            .unwrap()
+            .print(out)
+            .unwrap();
+    });
+    assert_eq!(
+        out,
+        "[1] NHK総合\n\
+         20:00 ~ 21:00 大河ドラマ\n\
+         21:00 ~ 22:00 ニュースウオッチ9\n\
+         [4] 日テレ\n\
+         20:54 ~ 24:00 金曜ロードショー\n\
+         [6] TBS\n\
+         23:00 ~ 24:30 深夜ドラマ\n"
+    );
+}
+
+#[test]
+fn test_today_tv_start_after_from_fixture() {
+    let out = |hour| {
+        let options = Options {
+            start_after: NaiveTime::from_hms_opt(hour, 0, 0),
+            ..options()
+        };
+        render(|out| {
+            TodayTv::from_html(ChannelSource::Area(42), TODAY, &options)
+                .unwrap()
+                .print(out)
+                .unwrap();
+        })
+    };
+    assert_eq!(
+        out(21),
+        "[1] NHK総合\n\
+         21:00 ~ 22:00 ニュースウオッチ9\n\
+         [4] 日テレ\n\
+         [6] TBS\n\
+         23:00 ~ 24:30 深夜ドラマ\n"
+    );
+    // 放送日が切り替わる5時より前は翌日の深夜
+    assert_eq!(out(1), "[1] NHK総合\n[4] 日テレ\n[6] TBS\n");
+}
+
+#[test]
+fn test_today_tv_programs_from_fixture() {
+    let options = Options {
+        format: Format::Json,
+        detail: true,
+        ..options()
+    };
+    let out = render(|out| {
+        TodayTv::from_html(ChannelSource::Area(42), TODAY, &options)
+            .unwrap()
+            .print(out)
+            .unwrap();
+    });
+    let programs: serde_json::Value = serde_json::from_str(&out).unwrap();
+    let programs = programs.as_array().unwrap();
+    assert_eq!(programs.len(), 4);
+    assert_eq!(
+        programs[0],
+        serde_json::json!({
+            "channel": "[1] NHK総合",
+            "start": "2024-01-01T20:00:00",
+            "end": "2024-01-01T21:00:00",
+            "title": "大河ドラマ",
+            "detail": "第1回「旅立ち」",
+        })
+    );
+    assert_eq!(programs[2]["channel"], "[4] 日テレ");
+    assert_eq!(programs[2]["end"], "2024-01-02T00:00:00");
+    assert_eq!(programs[3]["detail"], serde_json::Value::Null);
+}
+
+#[test]
+fn test_week_tv_from_fixtures() {
+    let options = options();
+    let out = render(|out| {
+        WeekTv::from_htmls(&[TODAY, BS], &options)
+            .unwrap()
+            .print(out)
+            .unwrap();
+    });
+    assert_eq!(
+        out,
+        "[1] NHK総合 Mon 20:00 ~ Mon 21:00 大河ドラマ\n\
+         [1] NHK総合 Mon 21:00 ~ Mon 22:00 ニュースウオッチ9\n\
+         [4] 日テレ Mon 20:54 ~ Tue 00:00 金曜ロードショー\n\
+         [6] TBS Mon 23:00 ~ Tue 00:30 深夜ドラマ\n\
+         [141] BS日テレ Mon 21:00 ~ Mon 22:00 ドラマ\n\
+         [151] BS朝日 Mon 21:00 ~ Mon 21:54 世界の車窓から\n"
+    );
+}
+
+#[test]
+fn test_week_tv_skips_empty_days() {
+    let options = options();
+    let out = render(|out| {
+        WeekTv::from_htmls(&[BS, EMPTY_DAY, EMPTY_DAY], &options)
+            .unwrap()
+            .print(out)
+            .unwrap();
+    });
+    assert_eq!(
+        out,
+        "[141] BS日テレ Mon 21:00 ~ Mon 22:00 ドラマ\n\
+         [151] BS朝日 Mon 21:00 ~ Mon 21:54 世界の車窓から\n"
+    );
+}
+
+#[test]
+fn test_channel_list_from_fixture() {
+    let options = options();
+    let out = render(|out| {
+        ChannelList::from_html(ChannelSource::Bs, BS, &options)
+            .unwrap()
+            .print(out)
+            .unwrap();
+    });
+    assert_eq!(out, "[141] BS日テレ\n[151] BS朝日\n");
+}
+
+#[test]
+fn test_malformed_fixture_fails() {
+    let options = options();
+    assert!(matches!(
+        Tv::from_html::<Vec<u8>>(ChannelSource::Area(42), MALFORMED, &options),
+        Err(TvnowError::Structure)
+    ));
+    assert!(matches!(
+        WeekTv::from_htmls::<Vec<u8>, _>(&[TODAY, MALFORMED], &options),
+        Err(TvnowError::Structure)
+    ));
+
+    let strict = Options {
+        strict: true,
+        ..options
+    };
+    assert!(
+        TodayTv::from_html::<Vec<u8>>(ChannelSource::Area(42), EMPTY_TITLE, &options()).is_ok()
+    );
+    assert!(matches!(
+        TodayTv::from_html::<Vec<u8>>(ChannelSource::Area(42), EMPTY_TITLE, &strict),
+        Err(TvnowError::Strict(_))
+    ));
+}
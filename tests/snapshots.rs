@@ -0,0 +1,114 @@
+mod common;
+
+use common::{options, render, BS, TODAY};
+use std::env;
+use std::fs;
+use std::path::Path;
+use tvnow::epg::{ChannelSource, Format, Options, Printer, SortOrder, Template, TodayTv, WeekTv};
+
+fn today(options: Options) -> String {
+    render(|out| {
+        TodayTv::from_html(ChannelSource::Area(42), TODAY, &options)
+            .unwrap()
+            .print(out)
+            .unwrap();
+    })
+}
+
+fn week(options: Options) -> String {
+    render(|out| {
+        WeekTv::from_htmls(&[TODAY, BS], &options)
+            .unwrap()
+            .print(out)
+            .unwrap();
+    })
+}
+
+/// `tests/snapshots/NAME`の期待する出力と比べる
+/// `UPDATE_SNAPSHOTS=1`で実行すると今の出力で書き換える
+fn assert_snapshot(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("snapshots")
+        .join(name);
+    // iCalendarのCRLFはファイルではLFにそろえる
+    let actual = actual.replace("\r\n", "\n");
+    if env::var_os("UPDATE_SNAPSHOTS").is_some() {
+        fs::write(&path, &actual).unwrap();
+        return;
+    }
+    let expected = fs::read_to_string(&path).unwrap_or_else(|err| {
+        panic!(
+            "{}: {} (run with UPDATE_SNAPSHOTS=1 to create it)",
+            path.display(),
+            err
+        )
+    });
+    assert_eq!(
+        actual, expected,
+        "{} differs from the output (run with UPDATE_SNAPSHOTS=1 to accept it)",
+        name
+    );
+}
+
+#[test]
+fn test_today_tv_snapshots() {
+    assert_snapshot(
+        "today_ascii.txt",
+        &today(Options {
+            ascii: true,
+            ..options()
+        }),
+    );
+    assert_snapshot(
+        "today_by_time.txt",
+        &today(Options {
+            sort: SortOrder::Time,
+            ..options()
+        }),
+    );
+    assert_snapshot(
+        "today_template.txt",
+        &today(Options {
+            template: Some(
+                "{date} {start} {channel} {title}"
+                    .parse::<Template>()
+                    .unwrap(),
+            ),
+            ..options()
+        }),
+    );
+}
+
+#[test]
+fn test_today_tv_format_snapshots() {
+    let format = |format| {
+        today(Options {
+            format,
+            ..options()
+        })
+    };
+    assert_snapshot("today.csv", &format(Format::Csv));
+    assert_snapshot("today.md", &format(Format::Markdown));
+    assert_snapshot("today.ics", &format(Format::Ical));
+    assert_snapshot(
+        "today_utc.csv",
+        &today(Options {
+            format: Format::Csv,
+            utc: true,
+            ..options()
+        }),
+    );
+}
+
+#[test]
+fn test_week_tv_snapshots() {
+    assert_snapshot(
+        "week_flat.txt",
+        &week(Options {
+            flat: true,
+            sort: SortOrder::Time,
+            ..options()
+        }),
+    );
+}